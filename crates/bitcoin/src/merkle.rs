@@ -1,5 +1,5 @@
 use crate::parser::BytesParser;
-use crate::types::{BlockHeader, Error, H256Le, CompactUint};
+use crate::types::{BlockHeader, Error, H256Le, CompactUint, Transaction};
 
 use bitcoin_spv::btcspv::hash256_merkle_step;
 
@@ -12,7 +12,15 @@ use mocktopus::macros::mockable;
 const MAX_BLOCK_WEIGHT: u32 = 4000000;
 const WITNESS_SCALE_FACTOR: u32 = 4;
 const MIN_TRANSACTION_WEIGHT: u32 = WITNESS_SCALE_FACTOR * 60;
-const MAX_TRANSACTIONS_IN_PROOF: u32 = MAX_BLOCK_WEIGHT / MIN_TRANSACTION_WEIGHT;
+pub(crate) const MAX_TRANSACTIONS_IN_BLOCK: u32 = MAX_BLOCK_WEIGHT / MIN_TRANSACTION_WEIGHT;
+const MAX_TRANSACTIONS_IN_PROOF: u32 = MAX_TRANSACTIONS_IN_BLOCK;
+
+/// Returns the maximum number of flag bytes of a partial merkle tree
+/// with the given number of transactions: the tree has at most
+/// 2 * transactions_count - 1 nodes, each using a single flag bit
+fn max_flag_bytes(transactions_count: u32) -> u64 {
+    (2 * transactions_count as u64 - 1 + 7) / 8
+}
 
 /// Struct to store the content of a merkle proof
 #[derive(Clone)]
@@ -62,7 +70,10 @@ impl MerkleProof {
         pos: u32,
         traversal: &mut MerkleProofTraversal,
     ) -> Result<H256Le, Error> {
-        let parent_of_hash = self.flag_bits[traversal.bits_used];
+        let parent_of_hash = *self
+            .flag_bits
+            .get(traversal.bits_used)
+            .ok_or(Error::MalformedProof)?;
         traversal.bits_used += 1;
 
         if height == 0 || !parent_of_hash {
@@ -80,7 +91,14 @@ impl MerkleProof {
 
         let left = self.traverse_and_extract(height - 1, pos * 2, traversal)?;
         let right = if pos * 2 + 1 < self.compute_tree_width(height - 1) {
-            self.traverse_and_extract(height - 1, pos * 2 + 1, traversal)?
+            let right = self.traverse_and_extract(height - 1, pos * 2 + 1, traversal)?;
+            // an existing right node equal to its left sibling is a
+            // duplicated hash, i.e. a mutated tree (CVE-2012-2459);
+            // only odd-width levels may repeat the left node
+            if right == left {
+                return Err(Error::MalformedProof);
+            }
+            right
         } else {
             left
         };
@@ -89,15 +107,12 @@ impl MerkleProof {
         Ok(H256Le::from_bytes_le(&hashed_bytes))
     }
 
-    /// Computes the merkle root of the proof partial merkle tree
-    pub fn verify_proof(&self) -> Result<ProofResult, Error> {
-        let mut traversal = MerkleProofTraversal {
-            bits_used: 0,
-            hashes_used: 0,
-            merkle_position: None,
-            hash_position: None,
-        };
-
+    /// Checks that the transactions count, hashes and flag bits of the
+    /// proof are consistent with each other, without traversing the tree
+    ///
+    /// This is a subset of the checks of `verify_proof`, allowing clients
+    /// to reject a malformed proof before submitting it
+    pub fn validate(&self) -> Result<(), Error> {
         // fail if no transactions
         if self.transactions_count == 0 {
             return Err(Error::MalformedProof);
@@ -113,6 +128,35 @@ impl MerkleProof {
             return Err(Error::MalformedProof);
         }
 
+        // fail if there are more hashes than transactions
+        if self.hashes.len() as u64 > self.transactions_count as u64 {
+            return Err(Error::MalformedProof);
+        }
+
+        // fail if there are more flag bits than nodes in the tree
+        if self.flag_bits.len() as u64 > 8 * max_flag_bytes(self.transactions_count) {
+            return Err(Error::MalformedProof);
+        }
+
+        Ok(())
+    }
+
+    /// Computes the merkle root of the proof partial merkle tree
+    ///
+    /// Proofs for the coinbase transaction (the leftmost leaf, position 0)
+    /// need no special handling. If the block only contains the coinbase
+    /// transaction, the tree has height 0 and the proof is a single hash
+    /// with a single set flag bit: the coinbase txid is the merkle root.
+    pub fn verify_proof(&self) -> Result<ProofResult, Error> {
+        self.validate()?;
+
+        let mut traversal = MerkleProofTraversal {
+            bits_used: 0,
+            hashes_used: 0,
+            merkle_position: None,
+            hash_position: None,
+        };
+
         let root = self.traverse_and_extract(self.compute_tree_height(), 0, &mut traversal)?;
         let merkle_position = traversal.merkle_position.ok_or(Error::InvalidProof)?;
         let hash_position = traversal.hash_position.ok_or(Error::InvalidProof)?;
@@ -149,12 +193,30 @@ impl MerkleProof {
     ///
     /// * `merkle_proof` - Raw bytes of the merkle proof
     pub fn parse(merkle_proof: &[u8]) -> Result<MerkleProof, Error> {
+        // a truncated proof is malformed
+        MerkleProof::parse_proof(merkle_proof).map_err(|e| match e {
+            Error::EOS => Error::MalformedProof,
+            e => e,
+        })
+    }
+
+    fn parse_proof(merkle_proof: &[u8]) -> Result<MerkleProof, Error> {
         let mut proof_parser = BytesParser::new(merkle_proof);
         let header = proof_parser.parse()?;
-        let transactions_count = proof_parser.parse()?;
+        let transactions_count: u32 = proof_parser.parse()?;
+
+        // fail early on impossible transaction counts, before allocating
+        if transactions_count == 0 || transactions_count > MAX_TRANSACTIONS_IN_PROOF {
+            return Err(Error::MalformedProof);
+        }
 
         let hashes_count: CompactUint = proof_parser.parse()?;
 
+        // there can not be more hashes than transactions in the block
+        if hashes_count.value > transactions_count as u64 {
+            return Err(Error::MalformedProof);
+        }
+
         let mut hashes = Vec::<H256Le>::new();
         for _ in 0..hashes_count.value {
             hashes.push(proof_parser.parse()?);
@@ -162,6 +224,12 @@ impl MerkleProof {
 
         let flag_bits_count: CompactUint = proof_parser.parse()?;
 
+        if flag_bits_count.value > max_flag_bytes(transactions_count)
+            || flag_bits_count.value > proof_parser.remaining() as u64
+        {
+            return Err(Error::MalformedProof);
+        }
+
         let mut flag_bits = Vec::new();
         for _ in 0..flag_bits_count.value {
             flag_bits.extend(proof_parser.parse::<Vec<bool>>()?);
@@ -176,10 +244,105 @@ impl MerkleProof {
     }
 }
 
+/// Computes the merkle root from a leaf hash, its position in the block
+/// and the sibling hashes on the path from the leaf to the root
+///
+/// # Arguments
+///
+/// * `leaf` - hash of the transaction
+/// * `index` - position of the transaction in the block
+/// * `siblings` - sibling hashes, starting at the leaf level
+pub fn compute_root_from_path(
+    leaf: H256Le,
+    index: u32,
+    siblings: &[H256Le],
+) -> Result<H256Le, Error> {
+    let mut hash = leaf;
+    let mut position = index;
+    for sibling in siblings {
+        let hashed_bytes = if position & 1 == 0 {
+            hash256_merkle_step(&hash.to_bytes_le(), &sibling.to_bytes_le())
+        } else {
+            hash256_merkle_step(&sibling.to_bytes_le(), &hash.to_bytes_le())
+        };
+        hash = H256Le::from_bytes_le(&hashed_bytes);
+        position >>= 1;
+    }
+    // fail if the index does not fit in the tree
+    if position != 0 {
+        return Err(Error::InvalidProof);
+    }
+    Ok(hash)
+}
+
+/// Computes the merkle root of a block from the ordered list of its
+/// transaction hashes, duplicating the last hash of each level with an
+/// odd number of nodes as in the official Bitcoin client
+/// https://github.com/bitcoin/bitcoin/blob/99813a9745fe10a58bedd7a4cb721faf14f907a4/src/consensus/merkle.cpp
+///
+/// The merkle root of a block with a single (coinbase) transaction is the
+/// hash of that transaction. An empty list yields the zero hash.
+///
+/// # Arguments
+///
+/// * `txids` - the transaction hashes of the block in block order
+pub fn compute_merkle_root(txids: &[H256Le]) -> H256Le {
+    if txids.is_empty() {
+        return H256Le::zero();
+    }
+
+    let mut level = txids.to_vec();
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| {
+                let left = pair[0];
+                let right = *pair.get(1).unwrap_or(&left);
+                let hashed_bytes =
+                    hash256_merkle_step(&left.to_bytes_le(), &right.to_bytes_le());
+                H256Le::from_bytes_le(&hashed_bytes)
+            })
+            .collect();
+    }
+    level[0]
+}
+
+/// Checks that the merkle root of a block header commits to the given
+/// transactions. Blocks listing the same transaction twice are rejected,
+/// as duplicating the last transactions of a level does not change the
+/// merkle root (CVE-2012-2459).
+///
+/// # Arguments
+///
+/// * `block_header` - the parsed header of the block
+/// * `transactions` - the transactions of the block in block order
+pub fn verify_block_merkle_root(
+    block_header: &BlockHeader,
+    transactions: &[Transaction],
+) -> Result<(), Error> {
+    let txids: Vec<H256Le> = transactions
+        .iter()
+        .map(|transaction| Transaction::tx_id(&transaction.to_legacy_bytes()))
+        .collect();
+
+    let mut sorted_txids: Vec<[u8; 32]> = txids.iter().map(|txid| txid.to_bytes_le()).collect();
+    sorted_txids.sort_unstable();
+    sorted_txids.dedup();
+    if sorted_txids.len() != txids.len() {
+        return Err(Error::MalformedBlock);
+    }
+
+    if compute_merkle_root(&txids) != block_header.merkle_root {
+        return Err(Error::InvalidMerkleRoot);
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use crate::parser::parse_transaction;
     use bitcoin_spv::utils::deserialize_hex;
     use primitive_types::H256;
     use std::str::FromStr;
@@ -196,6 +359,16 @@ mod tests {
 
     const PROOF_HEX: &str = "00000020ecf348128755dbeea5deb8eddf64566d9d4e59bc65d485000000000000000000901f0d92a66ee7dcefd02fa282ca63ce85288bab628253da31ef259b24abe8a0470a385a45960018e8d672f8a90a00000d0bdabada1fb6e3cef7f5c6e234621e3230a2f54efc1cba0b16375d9980ecbc023cbef3ba8d8632ea220927ec8f95190b30769eb35d87618f210382c9445f192504074f56951b772efa43b89320d9c430b0d156b93b7a1ff316471e715151a0619a39392657f25289eb713168818bd5b37476f1bc59b166deaa736d8a58756f9d7ce2aef46d8004c5fe3293d883838f87b5f1da03839878895b71530e9ff89338bb6d4578b3c3135ff3e8671f9a64d43b22e14c2893e8271cecd420f11d2359307403bb1f3128885b3912336045269ef909d64576b93e816fa522c8c027fe408700dd4bdee0254c069ccb728d3516fe1e27578b31d70695e3e35483da448f3a951273e018de7f2a8f657064b013c6ede75c74bbd7f98fdae1c2ac6789ee7b21a791aa29d60e89fff2d1d2b1ada50aa9f59f403823c8c58bb092dc58dc09b28158ca15447da9c3bedb0b160f3fe1668d5a27716e27661bcb75ddbf3468f5c76b7bed1004c6b4df4da2ce80b831a7c260b515e6355e1c306373d2233e8de6fda3674ed95d17a01a1f64b27ba88c3676024fbf8d5dd962ffc4d5e9f3b1700763ab88047f7d0000";
 
+    // block 170: https://www.blockchain.com/btc/block/00000000d1145790a8694403d4063f323d499e655c83426834d4ce2f8dd4a2ee
+    // proof of the coinbase transaction, the block contains 2 transactions
+    const COINBASE_PROOF_HEX: &str = "0100000055bd840a78798ad0da853f68974f3d183e2bd1db6a842c1feecf222a00000000ff104ccb05421ab93e63f8c3ce5c2c2e9dbb37de2764b3a3175c8166562cac7d51b96a49ffff001d283e9e7002000000\
+        0282501c1178fa0b222c1f3d474ec726b832013f0a532b44bb620cce8624a5feb1169e1e83e930853391bc6f35f605c6754cfead57cf8387639d3b4096c54f18f40103";
+
+    // block 0: https://www.blockchain.com/btc/block/000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f
+    // proof of the coinbase transaction, the only transaction in the block
+    const GENESIS_PROOF_HEX: &str = "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c01000000\
+        013ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a0101";
+
     fn sample_valid_proof_result() -> ProofResult {
         let tx_id = H256Le::from_bytes_le(&hex::decode("c8589f304d3b9df1d4d8b3d15eb6edaaa2af9d796e9d9ace12b31f293705c5e9".to_owned()).unwrap());
         let merkle_root = H256Le::from_bytes_le(&hex::decode("90d079ef103a8b7d3d9315126468f78b456690ba6628d1dcd5a16c9990fbe11e".to_owned()).unwrap());
@@ -236,6 +409,269 @@ mod tests {
         assert_eq!(proof.flag_bits.len(), 4 * 8);
     }
 
+    #[test]
+    fn test_parse_proof_absurd_transactions_count_fails() {
+        let mut raw_proof = deserialize_hex(&PROOF_HEX[..]).unwrap();
+        // transactions count follows the 80 byte block header
+        raw_proof[80..84].copy_from_slice(&2_000_000_000u32.to_le_bytes());
+        assert_eq!(MerkleProof::parse(&raw_proof).err(), Some(Error::MalformedProof));
+    }
+
+    #[test]
+    fn test_verify_proof_absurd_transactions_count_fails() {
+        let mut proof = MerkleProof::parse(&deserialize_hex(&PROOF_HEX[..]).unwrap()).unwrap();
+        proof.transactions_count = 2_000_000_000;
+        assert_eq!(proof.verify_proof().err(), Some(Error::MalformedProof));
+    }
+
+    #[test]
+    fn test_parse_proof_short_buffer_fails() {
+        let raw_proof = vec![0u8; 50];
+        assert_eq!(MerkleProof::parse(&raw_proof).err(), Some(Error::MalformedProof));
+    }
+
+    #[test]
+    fn test_parse_proof_truncated_fails() {
+        let raw_proof = deserialize_hex(&PROOF_HEX[..]).unwrap();
+        // truncated in the hashes, the flag bits count and the flag bits
+        for length in &[84, 120, raw_proof.len() - 5, raw_proof.len() - 1] {
+            assert_eq!(
+                MerkleProof::parse(&raw_proof[..*length]).err(),
+                Some(Error::MalformedProof)
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_proof_inflated_flag_bytes_count_fails() {
+        let raw_proof = deserialize_hex(&PROOF_HEX[..]).unwrap();
+        // flag bytes count precedes the 4 flag bytes
+        let flag_count_position = raw_proof.len() - 5;
+        assert_eq!(raw_proof[flag_count_position], 4);
+
+        // more flag bytes than left in the buffer
+        let mut inflated_proof = raw_proof.clone();
+        inflated_proof[flag_count_position] = 100;
+        assert_eq!(MerkleProof::parse(&inflated_proof).err(), Some(Error::MalformedProof));
+
+        // more flag bytes than nodes in the tree
+        let mut inflated_proof = raw_proof[..flag_count_position].to_vec();
+        inflated_proof.extend_from_slice(&[0xfd, 0x00, 0x10]);
+        inflated_proof.extend(vec![0xff; 4096]);
+        assert_eq!(MerkleProof::parse(&inflated_proof).err(), Some(Error::MalformedProof));
+    }
+
+    #[test]
+    fn test_verify_proof_missing_flag_bits_fails() {
+        let mut proof = MerkleProof::parse(&deserialize_hex(&PROOF_HEX[..]).unwrap()).unwrap();
+        // enough bits to reach the first leaf, but not to traverse the rest
+        proof.flag_bits = vec![true; proof.hashes.len()];
+        assert_eq!(proof.verify_proof().err(), Some(Error::MalformedProof));
+    }
+
+    #[test]
+    fn test_verify_proof_more_hashes_than_transactions_fails() {
+        let mut proof = MerkleProof::parse(&deserialize_hex(&PROOF_HEX[..]).unwrap()).unwrap();
+        proof.transactions_count = proof.hashes.len() as u32 - 1;
+        assert_eq!(proof.verify_proof().err(), Some(Error::MalformedProof));
+    }
+
+    #[test]
+    fn test_verify_proof_more_flag_bits_than_nodes_fails() {
+        let mut proof = MerkleProof::parse(&deserialize_hex(&PROOF_HEX[..]).unwrap()).unwrap();
+        // 2729 transactions make at most 5457 nodes, i.e. 683 flag bytes
+        proof.flag_bits = vec![true; 684 * 8];
+        assert_eq!(proof.verify_proof().err(), Some(Error::MalformedProof));
+    }
+
+    #[test]
+    fn test_validate_proof() {
+        let proof = MerkleProof::parse(&deserialize_hex(&PROOF_HEX[..]).unwrap()).unwrap();
+        assert_eq!(proof.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_proof_no_transactions_fails() {
+        let mut proof = MerkleProof::parse(&deserialize_hex(&PROOF_HEX[..]).unwrap()).unwrap();
+        proof.transactions_count = 0;
+        assert_eq!(proof.validate(), Err(Error::MalformedProof));
+    }
+
+    #[test]
+    fn test_validate_proof_too_many_transactions_fails() {
+        let mut proof = MerkleProof::parse(&deserialize_hex(&PROOF_HEX[..]).unwrap()).unwrap();
+        proof.transactions_count = MAX_TRANSACTIONS_IN_PROOF + 1;
+        assert_eq!(proof.validate(), Err(Error::MalformedProof));
+    }
+
+    #[test]
+    fn test_validate_proof_fewer_flag_bits_than_hashes_fails() {
+        let mut proof = MerkleProof::parse(&deserialize_hex(&PROOF_HEX[..]).unwrap()).unwrap();
+        proof.flag_bits = vec![true; proof.hashes.len() - 1];
+        assert_eq!(proof.validate(), Err(Error::MalformedProof));
+    }
+
+    #[test]
+    fn test_validate_proof_more_hashes_than_transactions_fails() {
+        let mut proof = MerkleProof::parse(&deserialize_hex(&PROOF_HEX[..]).unwrap()).unwrap();
+        proof.transactions_count = proof.hashes.len() as u32 - 1;
+        assert_eq!(proof.validate(), Err(Error::MalformedProof));
+    }
+
+    #[test]
+    fn test_validate_proof_more_flag_bits_than_nodes_fails() {
+        let mut proof = MerkleProof::parse(&deserialize_hex(&PROOF_HEX[..]).unwrap()).unwrap();
+        proof.flag_bits = vec![true; 684 * 8];
+        assert_eq!(proof.validate(), Err(Error::MalformedProof));
+    }
+
+    #[test]
+    fn test_validate_proof_does_not_traverse() {
+        let mut proof = MerkleProof::parse(&deserialize_hex(&PROOF_HEX[..]).unwrap()).unwrap();
+        // consistent sizes, but too few bits to traverse the tree
+        proof.flag_bits = vec![true; proof.hashes.len()];
+        assert_eq!(proof.validate(), Ok(()));
+        assert_eq!(proof.verify_proof().err(), Some(Error::MalformedProof));
+    }
+
+    #[test]
+    fn test_verify_proof_coinbase_transaction() {
+        let proof = MerkleProof::parse(&deserialize_hex(&COINBASE_PROOF_HEX[..]).unwrap()).unwrap();
+        let result = proof.verify_proof().unwrap();
+
+        assert_eq!(result.transaction_position, 0);
+        assert_eq!(
+            result.transaction_hash,
+            H256Le::from_hex_be("b1fea52486ce0c62bb442b530a3f0132b826c74e473d1f2c220bfa78111c5082")
+        );
+        assert_eq!(result.extracted_root, proof.block_header.merkle_root);
+    }
+
+    #[test]
+    fn test_verify_proof_coinbase_only_block() {
+        let proof = MerkleProof::parse(&deserialize_hex(&GENESIS_PROOF_HEX[..]).unwrap()).unwrap();
+        assert_eq!(proof.compute_tree_height(), 0);
+
+        let result = proof.verify_proof().unwrap();
+        let coinbase_txid = H256Le::from_hex_be("4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b");
+        assert_eq!(result.transaction_position, 0);
+        assert_eq!(result.transaction_hash, coinbase_txid);
+        assert_eq!(result.extracted_root, coinbase_txid);
+        assert_eq!(result.extracted_root, proof.block_header.merkle_root);
+    }
+
+    #[test]
+    fn test_verify_proof_duplicated_hash_fails() {
+        // CVE-2012-2459: duplicating the last hash of an even-width
+        // level yields a tree with a different set of transactions
+        let mut proof = MerkleProof::parse(&deserialize_hex(&COINBASE_PROOF_HEX[..]).unwrap()).unwrap();
+        proof.hashes[1] = proof.hashes[0];
+        assert_eq!(proof.verify_proof().err(), Some(Error::MalformedProof));
+
+        let mut proof = MerkleProof::parse(&deserialize_hex(&COINBASE_PROOF_HEX[..]).unwrap()).unwrap();
+        proof.hashes[0] = proof.hashes[1];
+        assert_eq!(proof.verify_proof().err(), Some(Error::MalformedProof));
+    }
+
+    #[test]
+    fn test_compute_merkle_root_single_transaction() {
+        // genesis block: the merkle root is the coinbase txid
+        let coinbase_txid =
+            H256Le::from_hex_be("4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b");
+        assert_eq!(compute_merkle_root(&[coinbase_txid]), coinbase_txid);
+    }
+
+    #[test]
+    fn test_compute_merkle_root_two_transactions() {
+        // block 170 contains exactly the two transactions of the proof
+        let proof = MerkleProof::parse(&deserialize_hex(&COINBASE_PROOF_HEX[..]).unwrap()).unwrap();
+        assert_eq!(proof.transactions_count, 2);
+        assert_eq!(
+            compute_merkle_root(&proof.hashes),
+            proof.block_header.merkle_root
+        );
+    }
+
+    #[test]
+    fn test_compute_merkle_root_four_transactions() {
+        // block 100000
+        let txids: Vec<H256Le> = [
+            "8c14f0db3df150123e6f3dbbf30f8b955a8249b62ac1d1ff16284aefa3d06d87",
+            "fff2525b8931402dd09222c50775608f75787bd2b87e56995a7bdd30f79702c4",
+            "6359f0868171b1d194cbee1af2f16ea598ae8fad666d9b012c8ed2b79a236ec4",
+            "e9a66845e05d5abc0ad04ec80f774a7e585c6e8db975962d069a522137b80c1d",
+        ]
+        .iter()
+        .map(|txid| H256Le::from_hex_be(txid))
+        .collect();
+        assert_eq!(
+            compute_merkle_root(&txids),
+            H256Le::from_hex_be("f3e94742aca4b5ef85488dc37c06c3282295ffec960994b2c0d5ac2a25a95766")
+        );
+    }
+
+    #[test]
+    fn test_compute_merkle_root_odd_transactions_count() {
+        let txids: Vec<H256Le> = (1..=3u8).map(|i| H256Le::from_bytes_le(&[i; 32])).collect();
+        let step = |left: H256Le, right: H256Le| {
+            H256Le::from_bytes_le(&hash256_merkle_step(&left.to_bytes_le(), &right.to_bytes_le()))
+        };
+
+        // the last hash is paired with itself
+        let expected_root = step(step(txids[0], txids[1]), step(txids[2], txids[2]));
+        assert_eq!(compute_merkle_root(&txids), expected_root);
+    }
+
+    // the two transactions of block 170, in block order
+    const BLOCK_170_TRANSACTIONS_HEX: [&str; 2] = [
+        "01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff0704ffff001d0102ffffffff0100f2052a01000000434104d46c4968bde02899d2aa0963367c7a6ce34eec332b32e42e5f3407e052d64ac625da6f0718e7b302140434bd725706957c092db53805b821a85b23a7ac61725bac00000000",
+        "0100000001c997a5e56e104102fa209c6a852dd90660a20b2d9c352423edce25857fcd3704000000004847304402204e45e16932b8af514961a1d3a1a25fdf3f4f7732e9d624c6c61548ab5fb8cd410220181522ec8eca07de4860a4acdd12909d831cc56cbbac4622082221a8768d1d0901ffffffff0200ca9a3b00000000434104ae1a62fe09c5f51b13905f07f06b99a2f7159b2225f374cd378d71302fa28414e7aab37397f554a7df5f142c21c1b7303b8a0626f1baded5c72a704f7e6cd84cac00286bee0000000043410411db93e1dcdb8a016b49840f8c53bc1eb68a382e97b1482ecad7b148a6909a5cb2e0eaddfb84ccf9744464f82e160bfa9b8b64f9d4c03f999b8643f656b412a3ac00000000",
+    ];
+
+    fn block_170_transactions() -> Vec<Transaction> {
+        BLOCK_170_TRANSACTIONS_HEX
+            .iter()
+            .map(|raw_tx| parse_transaction(&hex::decode(raw_tx).unwrap()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_verify_block_merkle_root_succeeds() {
+        let proof = MerkleProof::parse(&deserialize_hex(&COINBASE_PROOF_HEX[..]).unwrap()).unwrap();
+        assert_eq!(verify_block_merkle_root(&proof.block_header, &block_170_transactions()), Ok(()));
+    }
+
+    #[test]
+    fn test_verify_block_merkle_root_mismatch_fails() {
+        let proof = MerkleProof::parse(&deserialize_hex(&COINBASE_PROOF_HEX[..]).unwrap()).unwrap();
+        let mut transactions = block_170_transactions();
+        transactions.swap(0, 1);
+        assert_eq!(
+            verify_block_merkle_root(&proof.block_header, &transactions),
+            Err(Error::InvalidMerkleRoot)
+        );
+        assert_eq!(
+            verify_block_merkle_root(&proof.block_header, &transactions[..1]),
+            Err(Error::InvalidMerkleRoot)
+        );
+    }
+
+    #[test]
+    fn test_verify_block_merkle_root_duplicate_transaction_fails() {
+        let proof = MerkleProof::parse(&deserialize_hex(&COINBASE_PROOF_HEX[..]).unwrap()).unwrap();
+        let mut transactions = block_170_transactions();
+        transactions.push(transactions[1].clone());
+        assert_eq!(
+            verify_block_merkle_root(&proof.block_header, &transactions),
+            Err(Error::MalformedBlock)
+        );
+    }
+
+    #[test]
+    fn test_compute_merkle_root_empty() {
+        assert_eq!(compute_merkle_root(&[]), H256Le::zero());
+    }
+
     #[test]
     fn test_compute_tree_width() {
         let proof = MerkleProof::parse(&deserialize_hex(&PROOF_HEX[..]).unwrap()).unwrap();
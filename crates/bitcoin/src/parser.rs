@@ -1,3 +1,4 @@
+use crate::merkle::MAX_TRANSACTIONS_IN_BLOCK;
 use crate::types::*;
 
 use node_primitives::Moment;
@@ -54,8 +55,10 @@ make_parsable_int!(i64, 8);
 
 impl Parsable for CompactUint {
     fn parse(raw_bytes: &[u8], position: usize) -> Result<(CompactUint, usize), Error> {
-        let last_byte = std::cmp::min(position + 3, raw_bytes.len());
-        let (value, bytes_consumed) = parse_compact_uint(&raw_bytes[position..last_byte]);
+        if position > raw_bytes.len() {
+            return Err(Error::EOS);
+        }
+        let (value, bytes_consumed) = parse_compact_uint_checked(&raw_bytes[position..])?;
         Ok((CompactUint { value }, bytes_consumed))
     }
 }
@@ -109,6 +112,9 @@ impl<T, U: Copy> ParsableMeta<U> for Vec<T> where T: ParsableMeta<U> {
 
 impl Parsable for Vec<bool> {
     fn parse(raw_bytes: &[u8], position: usize) -> Result<(Vec<bool>, usize), Error> {
+        if position >= raw_bytes.len() {
+            return Err(Error::EOS);
+        }
         let byte = raw_bytes[position];
         let mut flag_bits = Vec::new();
         for i in 0..8 {
@@ -138,16 +144,16 @@ impl Parsable for TransactionOutput {
 
 /// BytesParser is a stateful parser for raw bytes
 /// The head of the parser is updated for each `read` or `parse` operation
-pub(crate) struct BytesParser {
-    raw_bytes: Vec<u8>,
+pub(crate) struct BytesParser<'a> {
+    raw_bytes: &'a [u8],
     position: usize,
 }
 
-impl BytesParser {
+impl<'a> BytesParser<'a> {
     /// Creates a new `BytesParser` to parse the given raw bytes
-    pub(crate) fn new(bytes: &[u8]) -> BytesParser {
+    pub(crate) fn new(bytes: &'a [u8]) -> BytesParser<'a> {
         BytesParser {
-            raw_bytes: Vec::from(bytes),
+            raw_bytes: bytes,
             position: 0,
         }
     }
@@ -157,7 +163,7 @@ impl BytesParser {
     /// Fails if there are not enough bytes to read or if the
     /// underlying `Parsable` parse function fails
     pub(crate) fn parse<T: Parsable>(&mut self) -> Result<T, Error> {
-        let (result, bytes_consumed) = T::parse(&self.raw_bytes, self.position)?;
+        let (result, bytes_consumed) = T::parse(self.raw_bytes, self.position)?;
         self.position += bytes_consumed;
         Ok(result)
     }
@@ -169,11 +175,26 @@ impl BytesParser {
     where
         T: ParsableMeta<U>,
     {
-        let (result, bytes_consumed) = T::parse_with(&self.raw_bytes, self.position, extra)?;
+        let (result, bytes_consumed) = T::parse_with(self.raw_bytes, self.position, extra)?;
         self.position += bytes_consumed;
         Ok(result)
     }
 
+    /// Returns the number of bytes left to parse
+    pub(crate) fn remaining(&self) -> usize {
+        self.raw_bytes.len().saturating_sub(self.position)
+    }
+
+    /// Moves the head `bytes_count` bytes forward without reading them
+    /// Fails if there are not enough bytes to skip
+    pub(crate) fn skip(&mut self, bytes_count: u64) -> Result<(), Error> {
+        if bytes_count > self.remaining() as u64 {
+            return Err(Error::EOS);
+        }
+        self.position += bytes_count as usize;
+        Ok(())
+    }
+
     /// Reads `bytes_count` from the bytes parser and moves the head
     /// Fails if there are not enough bytes to read
     pub(crate) fn read(&mut self, bytes_count: usize) -> Result<Vec<u8>, Error> {
@@ -230,14 +251,25 @@ pub fn extract_version(header: RawBlockHeader) -> u32 {
     u32::from_le_bytes(version)
 }
 
-/// Extracts the target from a block header.
+/// Extracts the compact nBits encoding of the target from a block header.
+///
+/// # Arguments
+///
+/// * `header` - An 80-byte Bitcoin header
+pub fn extract_bits(header: RawBlockHeader) -> u32 {
+    let mut bits: [u8; 4] = Default::default();
+    bits.copy_from_slice(&header[72..76]);
+    u32::from_le_bytes(bits)
+}
+
+/// Extracts the target from a block header. A negative or overflowing
+/// nBits encoding yields a zero target, which no block hash can meet.
 ///
 /// # Arguments
 ///
 /// * `header` - An 80-byte Bitcoin header
 pub fn extract_target(header: RawBlockHeader) -> U256 {
-    let target = btcspv::extract_target(header);
-    U256::from_little_endian(&target.to_bytes_le())
+    target_from_nbits(extract_bits(header)).unwrap_or_default()
 }
 
 /// Extracts the timestamp from a block header.
@@ -277,6 +309,7 @@ pub fn parse_block_header(raw_header: RawBlockHeader) -> BlockHeader {
     let block_header = BlockHeader {
         merkle_root: extract_merkle_root(raw_header),
         target: extract_target(raw_header),
+        bits: extract_bits(raw_header),
         timestamp: extract_timestamp(raw_header),
         version: extract_version(raw_header),
         nonce: extract_nonce(raw_header),
@@ -312,6 +345,26 @@ pub fn parse_compact_uint(varint: &[u8]) -> (u64, usize) {
     }
 }
 
+/// Returns the value of a compactly encoded uint and the number of bytes
+/// consumed, or `Error::EOS` if the slice is too short for the encoding
+///
+/// # Arguments
+///
+/// * `varint` - A slice starting with the compactly encoded uint
+pub fn parse_compact_uint_checked(varint: &[u8]) -> Result<(u64, usize), Error> {
+    let required_bytes = match varint.first() {
+        None => return Err(Error::EOS),
+        Some(0xfd) => 3,
+        Some(0xfe) => 5,
+        Some(0xff) => 9,
+        Some(_) => 1,
+    };
+    if varint.len() < required_bytes {
+        return Err(Error::EOS);
+    }
+    Ok(parse_compact_uint(varint))
+}
+
 /// Parses a single bitcoin transaction
 /// Serialization format is documented below
 /// https://github.com/bitcoin/bitcoin/blob/master/src/primitives/transaction.h#L182
@@ -320,7 +373,143 @@ pub fn parse_compact_uint(varint: &[u8]) -> (u64, usize) {
 /// * `raw_transaction` - the raw bytes of the transaction
 #[cfg_attr(test, mockable)]
 pub fn parse_transaction(raw_transaction: &[u8]) -> Result<Transaction, Error> {
+    BytesParser::new(raw_transaction).parse()
+}
+
+impl Parsable for Transaction {
+    fn parse(raw_bytes: &[u8], position: usize) -> Result<(Transaction, usize), Error> {
+        if position > raw_bytes.len() {
+            return Err(Error::EOS);
+        }
+        let mut parser = BytesParser::new(&raw_bytes[position..]);
+        let version: i32 = parser.parse()?;
+
+        // fail if incorrect version: we only support version 1 and 2
+        if version != 1 && version != 2 {
+            return Err(Error::MalformedTransaction);
+        }
+
+        let allow_witness = (version & SERIALIZE_TRANSACTION_NO_WITNESS) == 0;
+
+        // the SegWit marker (0x00) is read as an empty inputs vector,
+        // in which case it is followed by the flag and the actual inputs
+        let mut inputs: Vec<TransactionInput> = parser.parse_with(version)?;
+
+        let mut flags: u8 = 0;
+        if inputs.len() == 0 && allow_witness {
+            flags = parser.parse()?;
+            inputs = parser.parse_with(version)?;
+        }
+
+        let outputs: Vec<TransactionOutput> = parser.parse()?;
+
+        // the witness stacks of all inputs follow the outputs
+        if (flags & 1) != 0 && allow_witness {
+            flags ^= 1;
+            for input in &mut inputs {
+                input.with_witness(parser.parse::<Vec<Vec<u8>>>()?);
+            }
+        }
+
+        let locktime_or_blockheight: u32 = parser.parse()?;
+        let (locktime, block_height) = if locktime_or_blockheight < 500_000_000 {
+            (None, Some(locktime_or_blockheight))
+        } else {
+            (Some(locktime_or_blockheight), None)
+        };
+
+        if flags != 0 {
+            return Err(Error::MalformedTransaction);
+        }
+
+        let transaction = Transaction {
+            version: version,
+            inputs: inputs,
+            outputs: outputs,
+            block_height: block_height,
+            locktime: locktime,
+        };
+        Ok((transaction, parser.position))
+    }
+}
+
+/// Parses a full bitcoin block, i.e. the header followed by the
+/// transactions. The merkle root of the header is not checked, see
+/// `merkle::verify_block_merkle_root`.
+/// # Arguments
+///
+/// * `raw_block` - the raw bytes of the block
+pub fn parse_block(raw_block: &[u8]) -> Result<(BlockHeader, Vec<Transaction>), Error> {
+    let mut parser = BytesParser::new(raw_block);
+    let block_header: BlockHeader = parser.parse()?;
+
+    // fail early on impossible transaction counts, before allocating
+    let transactions_count = parser.parse::<CompactUint>()?.value;
+    if transactions_count == 0 || transactions_count > MAX_TRANSACTIONS_IN_BLOCK as u64 {
+        return Err(Error::MalformedBlock);
+    }
+
+    let mut transactions = Vec::with_capacity(transactions_count as usize);
+    for _ in 0..transactions_count {
+        transactions.push(parser.parse::<Transaction>()?);
+    }
+
+    if parser.remaining() != 0 {
+        return Err(Error::MalformedBlock);
+    }
+    Ok((block_header, transactions))
+}
+
+/// Parses only the outputs of a bitcoin transaction, skipping over the
+/// version and the inputs without parsing them.
+/// Unlike `parse_transaction`, this does not validate the inputs, nor
+/// the witnesses and the locktime following the outputs.
+/// # Arguments
+///
+/// * `raw_transaction` - the raw bytes of the transaction
+#[cfg_attr(test, mockable)]
+pub fn parse_transaction_outputs(raw_transaction: &[u8]) -> Result<Vec<TransactionOutput>, Error> {
+    let mut parser = BytesParser::new(raw_transaction);
+    skip_transaction_inputs(&mut parser)?;
+    parser.parse()
+}
+
+/// Returns the id of a bitcoin transaction, i.e. the hash of its
+/// serialization without the SegWit marker, flag and witnesses, without
+/// parsing the inputs.
+/// # Arguments
+///
+/// * `raw_transaction` - the raw bytes of the transaction
+pub fn extract_tx_id(raw_transaction: &[u8]) -> Result<H256Le, Error> {
     let mut parser = BytesParser::new(raw_transaction);
+    let layout = skip_transaction_inputs(&mut parser)?;
+    let _outputs: Vec<TransactionOutput> = parser.parse()?;
+    let outputs_end = parser.position;
+
+    if layout.has_witness {
+        for _ in 0..layout.inputs_count {
+            let _witness: Vec<Vec<u8>> = parser.parse()?;
+        }
+    }
+    let locktime = parser.read(4)?;
+
+    let mut legacy_bytes = Vec::from(&raw_transaction[..4]);
+    legacy_bytes.extend_from_slice(&raw_transaction[layout.inputs_start..outputs_end]);
+    legacy_bytes.extend(locktime);
+    Ok(Transaction::tx_id(&legacy_bytes))
+}
+
+/// Position and number of the inputs of a raw transaction
+struct InputsLayout {
+    /// position of the inputs count, after the SegWit marker and flag
+    inputs_start: usize,
+    inputs_count: u64,
+    has_witness: bool,
+}
+
+/// Skips the version and the inputs of a raw transaction, leaving the
+/// parser head at the outputs
+fn skip_transaction_inputs(parser: &mut BytesParser) -> Result<InputsLayout, Error> {
     let version: i32 = parser.parse()?;
 
     // fail if incorrect version: we only support version 1 and 2
@@ -330,40 +519,36 @@ pub fn parse_transaction(raw_transaction: &[u8]) -> Result<Transaction, Error> {
 
     let allow_witness = (version & SERIALIZE_TRANSACTION_NO_WITNESS) == 0;
 
-    let mut inputs: Vec<TransactionInput> = parser.parse_with(version)?;
+    // the SegWit marker (0x00) is read as an empty inputs vector,
+    // in which case it is followed by the flag and the actual inputs
+    let mut inputs_start = parser.position;
+    let mut inputs_count = parser.parse::<CompactUint>()?.value;
 
     let mut flags: u8 = 0;
-    if inputs.len() == 0 && allow_witness {
+    if inputs_count == 0 && allow_witness {
         flags = parser.parse()?;
-        inputs = parser.parse_with(version)?;
+        inputs_start = parser.position;
+        inputs_count = parser.parse::<CompactUint>()?.value;
     }
 
-    let outputs: Vec<TransactionOutput> = parser.parse()?;
-
-    if (flags & 1) != 0 && allow_witness {
-        flags ^= 1;
-        for input in &mut inputs {
-            input.with_witness(parser.parse()?);
-        }
+    // only the witness flag is supported
+    if (flags & !1) != 0 {
+        return Err(Error::MalformedTransaction);
     }
 
-    let locktime_or_blockheight: u32 = parser.parse()?;
-    let (locktime, block_height) = if locktime_or_blockheight < 500_000_000 {
-        (None, Some(locktime_or_blockheight))
-    } else {
-        (Some(locktime_or_blockheight), None)
-    };
-
-    if flags != 0 {
-        return Err(Error::MalformedTransaction);
+    for _ in 0..inputs_count {
+        // previous outpoint
+        parser.skip(36)?;
+        let script_size = parser.parse::<CompactUint>()?.value;
+        parser.skip(script_size)?;
+        // sequence
+        parser.skip(4)?;
     }
 
-    Ok(Transaction {
-        version: version,
-        inputs: inputs,
-        outputs: outputs,
-        block_height: block_height,
-        locktime: locktime,
+    Ok(InputsLayout {
+        inputs_start: inputs_start,
+        inputs_count: inputs_count,
+        has_witness: (flags & 1) != 0,
     })
 }
 
@@ -441,12 +626,17 @@ pub fn extract_address_hash(output_script: &[u8]) -> Result<Vec<u8>, Error> {
 
     let script_len = output_script.len();
     
-    // Witness
+    // Witness (BIP141)
+    // Format:
+    // 0x00 (version 0) - 0x14 (20 bytes len) - <20 bytes pubkey hash> (P2WPKH)
+    // 0x00 (version 0) - 0x20 (32 bytes len) - <32 bytes script hash> (P2WSH)
     if output_script[0] == 0 {
         if script_len < 2 {
             return Err(Error::MalformedWitnessOutput);
         }
-        if output_script[1] == (script_len - 2) as u8 {
+        let program_len = output_script[1] as usize;
+        if program_len == script_len - 2
+            && (program_len == P2WPKH_PROGRAM_SIZE || program_len == P2WSH_PROGRAM_SIZE) {
             return Ok(output_script[2..].to_vec());
         } else {
             return Err(Error::MalformedWitnessOutput);
@@ -477,8 +667,41 @@ pub fn extract_address_hash(output_script: &[u8]) -> Result<Vec<u8>, Error> {
     return Err(Error::UnsupportedOutputFormat);
 }
 
+/// Extracts the block height committed in a coinbase input (BIP34)
+///
+/// # Arguments
+///
+/// * `input` - the coinbase transaction input
+pub fn extract_coinbase_height(input: &TransactionInput) -> Result<u32, Error> {
+    if !input.coinbase {
+        return Err(Error::MalformedTransaction);
+    }
+    // for version 2 transactions the height push is split off the script
+    let script = match &input.height {
+        Some(height) => height,
+        None => &input.script,
+    };
+    let opcode = *script.get(0).ok_or(Error::EOS)?;
+
+    // heights 1 to 16 are pushed with OP_1 to OP_16
+    if opcode >= 0x51 && opcode <= 0x60 {
+        return Ok((opcode - 0x50) as u32);
+    }
+
+    let push_size = opcode as usize;
+    if push_size == 0 || push_size > 4 {
+        return Err(Error::MalformedTransaction);
+    }
+    if script.len() < push_size + 1 {
+        return Err(Error::EOS);
+    }
+    let mut height_bytes: [u8; 4] = Default::default();
+    height_bytes[..push_size].copy_from_slice(&script[1..=push_size]);
+    Ok(u32::from_le_bytes(height_bytes))
+}
+
 pub fn extract_op_return_data(output_script: &[u8]) -> Result<Vec<u8>, Error> {
-    if output_script[0] != OpCode::OpReturn as u8 {
+    if output_script.first() != Some(&(OpCode::OpReturn as u8)) {
         return Err(Error::MalformedOpReturnOutput);
     }
     // Check for max OP_RETURN size
@@ -487,7 +710,22 @@ pub fn extract_op_return_data(output_script: &[u8]) -> Result<Vec<u8>, Error> {
         return Err(Error::MalformedOpReturnOutput);
     }
 
-    Ok(output_script[2..].to_vec())
+    // Format:
+    // 0x6a (OP_RETURN) - 0x01..0x4b (push len) - <data>
+    // 0x6a (OP_RETURN) - 0x4c (OP_PUSHDATA1) - <1 byte len> - <data>
+    let (data_start, data_len) = match output_script.get(1) {
+        Some(&len) if len >= 0x01 && len < OpCode::OpPushData1 as u8 => (2, len as usize),
+        Some(&opcode) if opcode == OpCode::OpPushData1 as u8 => match output_script.get(2) {
+            Some(&len) => (3, len as usize),
+            None => return Err(Error::MalformedOpReturnOutput),
+        },
+        _ => return Err(Error::MalformedOpReturnOutput),
+    };
+    if data_len > MAX_OPRETURN_DATA_SIZE || output_script.len() != data_start + data_len {
+        return Err(Error::MalformedOpReturnOutput);
+    }
+
+    Ok(output_script[data_start..].to_vec())
 }
 
 
@@ -523,6 +761,7 @@ mod tests {
         let expected_target =
             String::from("680733321990486529407107157001552378184394215934016880640");
         assert_eq!(parsed_header.target.to_string(), expected_target);
+        assert_eq!(parsed_header.bits, 0x181bc330);
     }
 
     #[test]
@@ -541,6 +780,42 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_compact_uint_checked() {
+        let cases = [
+            (&[0xfc][..], (0xfc, 1)),
+            (&[0xfd, 0x02, 0x03][..], (770, 3)),
+            (&[0xfe, 0x02, 0x03, 0x08, 0x01][..], (17302274, 5)),
+            (
+                &[0xff, 0x06, 0x0a, 0x03, 0x08, 0x01, 0x0b, 0x02, 0x07][..],
+                (504978207276206598, 9),
+            ),
+        ];
+        for (input, expected) in cases.iter() {
+            assert_eq!(parse_compact_uint_checked(input), Ok(*expected));
+        }
+    }
+
+    #[test]
+    fn test_parse_compact_uint_checked_truncated() {
+        let cases = [
+            &[][..],
+            &[0xfd, 0x02][..],
+            &[0xfe, 0x02, 0x03, 0x08][..],
+            &[0xff, 0x06, 0x0a, 0x03, 0x08, 0x01, 0x0b, 0x02][..],
+        ];
+        for input in cases.iter() {
+            assert_eq!(parse_compact_uint_checked(input), Err(Error::EOS));
+        }
+    }
+
+    #[test]
+    fn test_parse_transaction_output_truncated_script_size() {
+        // value followed by a 0xfe script size prefix missing its payload
+        let raw_output = hex::decode("00e1f50500000000fe0100").unwrap();
+        assert_eq!(parse_transaction_output(&raw_output).err(), Some(Error::EOS));
+    }
+
     fn sample_coinbase_transaction_input() -> String {
         "00000000000000000000000000000000".to_owned() +
         "00000000000000000000000000000000" + // Previous outpoint TXID
@@ -666,7 +941,7 @@ mod tests {
         assert_eq!(transaction.version, 2);
         assert_eq!(inputs.len(), 1);
         assert_eq!(inputs[0].coinbase, true);
-        assert_eq!(inputs[0].witness.is_some(), true);
+        assert_eq!(inputs[0].witness, Some(vec![vec![0; 32]]));
         assert_eq!(outputs.len(), 2);
         assert_eq!(&hex::encode(&outputs[0].script), "a91466c7060feb882664ae62ffad0051fe843e318e8587");
         assert_eq!(&hex::encode(&outputs[1].script), "6a24aa21a9ede5c17d15b8b1fa2811b7e6da66ffa5e1aaa05922c69068bf90cd585b95bb4675");
@@ -674,6 +949,229 @@ mod tests {
         assert_eq!(transaction.locktime, None);
     }
 
+    #[test]
+    fn test_parse_transaction_outputs() {
+        let raw_transactions = [
+            hex::decode(&sample_transaction()).unwrap(),
+            // witness transaction
+            hex::decode("020000000001010000000000000000000000000000000000000000000000000000000000000000ffffffff0502cb000101ffffffff02400606950000000017a91466c7060feb882664ae62ffad0051fe843e318e85870000000000000000266a24aa21a9ede5c17d15b8b1fa2811b7e6da66ffa5e1aaa05922c69068bf90cd585b95bb46750120000000000000000000000000000000000000000000000000000000000000000000000000").unwrap(),
+        ];
+        for tx_bytes in raw_transactions.iter() {
+            let transaction = parse_transaction(&tx_bytes).unwrap();
+            assert_eq!(parse_transaction_outputs(&tx_bytes).unwrap(), transaction.outputs);
+            assert_eq!(
+                extract_tx_id(&tx_bytes).unwrap(),
+                Transaction::tx_id(&transaction.to_legacy_bytes())
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_transaction_outputs_truncated_fails() {
+        let tx_bytes = hex::decode(&sample_transaction()).unwrap();
+        // truncated in the second input and in the outputs
+        for length in &[100, tx_bytes.len() - 10] {
+            assert_eq!(parse_transaction_outputs(&tx_bytes[..*length]).err(), Some(Error::EOS));
+        }
+        // missing locktime
+        assert_eq!(extract_tx_id(&tx_bytes[..tx_bytes.len() - 1]).err(), Some(Error::EOS));
+    }
+
+    #[test]
+    fn test_parse_transaction_p2wpkh_spend() {
+        // native P2WPKH input of the BIP143 example transaction
+        let raw_tx = "01000000".to_owned() +
+            "0001" + // ................................ SegWit marker and flag
+            "01" + // .................................. Number of inputs
+            "ef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff" +
+            "02" + // .................................. Number of outputs
+            "202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac" +
+            "9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac" +
+            "02" + // .................................. Witness stack items of input 0
+            "47304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee01" +
+            "21025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee6357" +
+            "11000000"; // ............................. Locktime
+        let tx_bytes = hex::decode(&raw_tx).unwrap();
+        let transaction = parse_transaction(&tx_bytes).unwrap();
+        let inputs = transaction.inputs;
+        assert_eq!(transaction.version, 1);
+        assert_eq!(inputs.len(), 1);
+        assert_eq!(inputs[0].script.len(), 0);
+        let witness = inputs[0].witness.clone().unwrap();
+        assert_eq!(witness.len(), 2);
+        assert_eq!(witness[0].len(), 71); // signature
+        assert_eq!(&hex::encode(&witness[1]), "025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee6357");
+        assert_eq!(transaction.outputs.len(), 2);
+        assert_eq!(transaction.block_height, Some(17));
+    }
+
+    #[test]
+    fn test_parse_transaction_mixed_legacy_and_witness_inputs() {
+        // BIP143 native P2WPKH example: a P2PK input and a P2WPKH input
+        let raw_tx = "01000000000102fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f00000000494830450221008b9d1dc26ba6a9cb62127b02742fa9d754cd3bebf337f7a55d114c8e5cdd30be022040529b194ba3f9281a99f2b1c0a19c0489bc22ede944ccf4ecbab4cc618ef3ed01eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac000247304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee0121025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee635711000000";
+        let tx_bytes = hex::decode(&raw_tx).unwrap();
+        let transaction = parse_transaction(&tx_bytes).unwrap();
+        let inputs = transaction.inputs;
+        assert_eq!(inputs.len(), 2);
+        // legacy input: signature in the script and an empty witness stack
+        assert_eq!(inputs[0].script.len(), 73);
+        assert_eq!(inputs[0].witness, Some(vec![]));
+        // witness input: empty script, signature and pubkey in the witness
+        assert_eq!(inputs[1].script.len(), 0);
+        let witness = inputs[1].witness.clone().unwrap();
+        assert_eq!(witness.len(), 2);
+        assert_eq!(witness[1].len(), 33);
+        assert_eq!(transaction.outputs.len(), 2);
+        assert_eq!(transaction.block_height, Some(17));
+    }
+
+    #[test]
+    fn test_parse_transaction_legacy_has_no_witness() {
+        let tx_bytes = hex::decode(&sample_transaction()).unwrap();
+        let transaction = parse_transaction(&tx_bytes).unwrap();
+        assert!(transaction.inputs.iter().all(|input| input.witness.is_none()));
+    }
+
+    #[test]
+    fn test_transaction_to_legacy_bytes_round_trip() {
+        let tx_bytes = hex::decode(&sample_transaction()).unwrap();
+        let transaction = parse_transaction(&tx_bytes).unwrap();
+        assert_eq!(transaction.to_legacy_bytes(), tx_bytes);
+    }
+
+    #[test]
+    fn test_transaction_to_legacy_bytes_strips_witness() {
+        let raw_tx = "020000000001010000000000000000000000000000000000000000000000000000000000000000ffffffff0502cb000101ffffffff02400606950000000017a91466c7060feb882664ae62ffad0051fe843e318e85870000000000000000266a24aa21a9ede5c17d15b8b1fa2811b7e6da66ffa5e1aaa05922c69068bf90cd585b95bb46750120000000000000000000000000000000000000000000000000000000000000000000000000";
+        let tx_bytes = hex::decode(&raw_tx).unwrap();
+        let transaction = parse_transaction(&tx_bytes).unwrap();
+
+        // marker, flag and witness are removed
+        let legacy_bytes = transaction.to_legacy_bytes();
+        let mut expected = tx_bytes[..4].to_vec();
+        expected.extend_from_slice(&tx_bytes[6..tx_bytes.len() - 38]);
+        expected.extend_from_slice(&tx_bytes[tx_bytes.len() - 4..]);
+        assert_eq!(legacy_bytes, expected);
+
+        let mut legacy_transaction = parse_transaction(&legacy_bytes).unwrap();
+        assert_eq!(legacy_transaction.inputs[0].witness, None);
+        legacy_transaction.inputs[0].with_witness(vec![vec![0; 32]]);
+        assert_eq!(legacy_transaction, transaction);
+    }
+
+    // block 170: https://www.blockchain.com/btc/block/00000000d1145790a8694403d4063f323d499e655c83426834d4ce2f8dd4a2ee
+    // the coinbase and the first transaction between two people
+    const BLOCK_170_HEX: &str = "0100000055bd840a78798ad0da853f68974f3d183e2bd1db6a842c1feecf222a00000000ff104ccb05421ab93e63f8c3ce5c2c2e9dbb37de2764b3a3175c8166562cac7d51b96a49ffff001d283e9e7002\
+        01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff0704ffff001d0102ffffffff0100f2052a01000000434104d46c4968bde02899d2aa0963367c7a6ce34eec332b32e42e5f3407e052d64ac625da6f0718e7b302140434bd725706957c092db53805b821a85b23a7ac61725bac00000000\
+        0100000001c997a5e56e104102fa209c6a852dd90660a20b2d9c352423edce25857fcd3704000000004847304402204e45e16932b8af514961a1d3a1a25fdf3f4f7732e9d624c6c61548ab5fb8cd410220181522ec8eca07de4860a4acdd12909d831cc56cbbac4622082221a8768d1d0901ffffffff0200ca9a3b00000000434104ae1a62fe09c5f51b13905f07f06b99a2f7159b2225f374cd378d71302fa28414e7aab37397f554a7df5f142c21c1b7303b8a0626f1baded5c72a704f7e6cd84cac00286bee0000000043410411db93e1dcdb8a016b49840f8c53bc1eb68a382e97b1482ecad7b148a6909a5cb2e0eaddfb84ccf9744464f82e160bfa9b8b64f9d4c03f999b8643f656b412a3ac00000000";
+
+    #[test]
+    fn test_parse_block() {
+        let block_bytes = hex::decode(BLOCK_170_HEX).unwrap();
+        let (block_header, transactions) = parse_block(&block_bytes).unwrap();
+        assert_eq!(
+            block_header.merkle_root,
+            H256Le::from_hex_be("7dac2c5666815c17a3b36427de37bb9d2e2c5ccec3f8633eb91a4205cb4c10ff")
+        );
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(transactions[0].inputs[0].coinbase, true);
+        assert_eq!(transactions[1].inputs[0].coinbase, false);
+        assert_eq!(transactions[1].outputs.len(), 2);
+        assert_eq!(
+            Transaction::tx_id(&transactions[1].to_legacy_bytes()),
+            H256Le::from_hex_be("f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16")
+        );
+    }
+
+    #[test]
+    fn test_parse_block_trailing_bytes_fails() {
+        let mut block_bytes = hex::decode(BLOCK_170_HEX).unwrap();
+        block_bytes.push(0);
+        assert_eq!(parse_block(&block_bytes), Err(Error::MalformedBlock));
+    }
+
+    #[test]
+    fn test_parse_block_truncated_fails() {
+        let block_bytes = hex::decode(BLOCK_170_HEX).unwrap();
+        assert_eq!(parse_block(&block_bytes[..block_bytes.len() - 1]), Err(Error::EOS));
+    }
+
+    #[test]
+    fn test_parse_block_transactions_count_out_of_bounds_fails() {
+        let mut block_bytes = hex::decode(&BLOCK_170_HEX[..160]).unwrap();
+        block_bytes.push(0);
+        assert_eq!(parse_block(&block_bytes), Err(Error::MalformedBlock));
+
+        // more transactions than fit in a block, no bytes are read for them
+        let mut block_bytes = hex::decode(&BLOCK_170_HEX[..160]).unwrap();
+        block_bytes.extend_from_slice(&[0xfe, 0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(parse_block(&block_bytes), Err(Error::MalformedBlock));
+    }
+
+    #[test]
+    fn test_extract_coinbase_height() {
+        let raw_input = sample_coinbase_transaction_input();
+        let input_bytes = bitcoin_spv::utils::deserialize_hex(&raw_input).unwrap();
+
+        // version 2: height is split off the script
+        let (input, _) = parse_transaction_input(&input_bytes, 2).unwrap();
+        assert_eq!(extract_coinbase_height(&input), Ok(328014));
+
+        // version 1: height is the first push of the script
+        let (input, _) = parse_transaction_input(&input_bytes, 1).unwrap();
+        assert_eq!(extract_coinbase_height(&input), Ok(328014));
+    }
+
+    #[test]
+    fn test_extract_coinbase_height_not_coinbase_fails() {
+        let raw_input = sample_transaction_input();
+        let input_bytes = bitcoin_spv::utils::deserialize_hex(&raw_input).unwrap();
+        let (input, _) = parse_transaction_input(&input_bytes, 2).unwrap();
+        assert_eq!(extract_coinbase_height(&input), Err(Error::MalformedTransaction));
+    }
+
+    #[test]
+    fn test_extract_op_return_data_pushdata1() {
+        let script = hex::decode("6a4c50".to_owned() + &"ab".repeat(80)).unwrap();
+        assert_eq!(extract_op_return_data(&script).unwrap(), vec![0xab; 80]);
+    }
+
+    #[test]
+    fn test_extract_op_return_data_invalid_push_fails() {
+        // push length does not match the script
+        let script = hex::decode("6a20aabb").unwrap();
+        assert_eq!(extract_op_return_data(&script), Err(Error::MalformedOpReturnOutput));
+        // no push at all
+        let script = hex::decode("6a").unwrap();
+        assert_eq!(extract_op_return_data(&script), Err(Error::MalformedOpReturnOutput));
+        // not an OP_RETURN output
+        let script = hex::decode("a91466c7060feb882664ae62ffad0051fe843e318e8587").unwrap();
+        assert_eq!(extract_op_return_data(&script), Err(Error::MalformedOpReturnOutput));
+    }
+
+    #[test]
+    fn test_extract_address_hash_valid_p2wpkh() {
+        let p2wpkh_script = hex::decode("0014751e76e8199196d454941c45d1b3a323f1433bd6").unwrap();
+        let p2wpkh_address = hex::decode("751e76e8199196d454941c45d1b3a323f1433bd6").unwrap();
+        assert_eq!(extract_address_hash(&p2wpkh_script).unwrap(), p2wpkh_address);
+    }
+
+    #[test]
+    fn test_extract_address_hash_valid_p2wsh() {
+        let p2wsh_script = hex::decode("00201863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262").unwrap();
+        let p2wsh_address = hex::decode("1863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262").unwrap();
+        assert_eq!(extract_address_hash(&p2wsh_script).unwrap(), p2wsh_address);
+    }
+
+    #[test]
+    fn test_extract_address_hash_invalid_witness_program_size_fails() {
+        // 16 byte program
+        let script = hex::decode("0010751e76e8199196d454941c45d1b3a323").unwrap();
+        assert_eq!(extract_address_hash(&script), Err(Error::MalformedWitnessOutput));
+        // length byte does not match the program
+        let script = hex::decode("0020751e76e8199196d454941c45d1b3a323f1433bd6").unwrap();
+        assert_eq!(extract_address_hash(&script), Err(Error::MalformedWitnessOutput));
+    }
+
     #[test]
     fn test_extract_address_hash_valid_p2pkh(){
         let p2pkh_script = bitcoin_spv::utils::deserialize_hex(&sample_valid_p2pkh()).unwrap();
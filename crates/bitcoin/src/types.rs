@@ -1,6 +1,6 @@
 extern crate hex;
 
-use primitive_types::{U256, H256};
+use primitive_types::{U256, H160, H256};
 use codec::{Encode, Decode};
 use node_primitives::{Moment};
 use sp_std::collections::btree_map::BTreeMap;
@@ -27,7 +27,25 @@ pub type RawBlockHeader = RawHeader;
 pub const P2PKH_SCRIPT_SIZE: u32 = 25;
 pub const P2SH_SCRIPT_SIZE: u32 = 23;
 pub const HASH160_SIZE_HEX: u8 = 0x14;
+pub const P2WPKH_PROGRAM_SIZE: usize = 20;
+pub const P2WSH_PROGRAM_SIZE: usize = 32;
 pub const MAX_OPRETURN_SIZE: usize = 83;
+pub const MAX_OPRETURN_DATA_SIZE: usize = 80;
+
+/// Bitcoin network the relay (or an address) belongs to
+#[derive(Encode, Decode, Copy, Clone, PartialEq, Eq, Debug)]
+pub enum BitcoinNetwork {
+    Mainnet,
+    Testnet,
+    Regtest,
+}
+
+impl Default for BitcoinNetwork {
+    fn default() -> Self {
+        BitcoinNetwork::Mainnet
+    }
+}
+
 /// Structs
 /// Bitcoin Basic Block Headers
 // TODO: Figure out how to set a pointer to the ChainIndex mapping instead
@@ -36,6 +54,8 @@ pub const MAX_OPRETURN_SIZE: usize = 83;
 pub struct BlockHeader {
     pub merkle_root: H256Le,
     pub target: U256,
+    /// Compact nBits encoding of the target as found in the raw header
+    pub bits: u32,
     pub timestamp: Moment,
     pub version: u32,
     pub hash_prev_block: H256Le,
@@ -51,6 +71,67 @@ impl BlockHeader {
     pub fn block_hash_be(bytes: &[u8]) -> H256{
         sha256d_be(bytes)
     }
+
+    /// Checks if the nBits field is the canonical encoding of the target,
+    /// i.e. the encoding Bitcoin Core produces. This rejects negative,
+    /// overflowing and non-normalized encodings.
+    pub fn has_canonical_bits(&self) -> bool {
+        self.bits == nbits_from_target(self.target)
+    }
+}
+
+/// Returns the target encoded in the compact nBits format
+/// See: https://github.com/bitcoin/bitcoin/blob/master/src/arith_uint256.cpp
+///
+/// # Arguments
+///
+/// * `target` - the 256-bit target
+pub fn nbits_from_target(target: U256) -> u32 {
+    let mut size = (target.bits() + 7) / 8;
+    let mut compact = if size <= 3 {
+        target.low_u64() << (8 * (3 - size))
+    } else {
+        (target >> (8 * (size - 3))).low_u64()
+    };
+    // the mantissa is signed, move a set sign bit to the exponent
+    if compact & 0x0080_0000 != 0 {
+        compact >>= 8;
+        size += 1;
+    }
+    (compact as u32) | ((size as u32) << 24)
+}
+
+/// Returns the target encoded by the compact nBits format
+/// See: https://github.com/bitcoin/bitcoin/blob/master/src/arith_uint256.cpp
+///
+/// # Arguments
+///
+/// * `nbits` - the compact encoding of the target
+///
+/// # Errors
+/// * `MalformedBits` - if the sign bit is set or the target exceeds 256 bits
+pub fn target_from_nbits(nbits: u32) -> Result<U256, Error> {
+    let size = nbits >> 24;
+    let mut word = nbits & 0x007f_ffff;
+    let target = if size <= 3 {
+        word >>= 8 * (3 - size);
+        U256::from(word)
+    } else if word != 0 && size > 34 {
+        // shifting by 256 bits or more would panic
+        return Err(Error::MalformedBits);
+    } else if word == 0 {
+        U256::zero()
+    } else {
+        U256::from(word) << (8 * (size - 3))
+    };
+
+    let negative = word != 0 && nbits & 0x0080_0000 != 0;
+    let overflow = word != 0
+        && (size > 34 || (word > 0xff && size > 33) || (word > 0xffff && size > 32));
+    if negative || overflow {
+        return Err(Error::MalformedBits);
+    }
+    Ok(target)
 }
 
 /// Bitcoin transaction input
@@ -62,13 +143,20 @@ pub struct TransactionInput {
     pub height: Option<Vec<u8>>, // FIXME: Vec<u8> type here seems weird
     pub script: Vec<u8>,
     pub sequence: u32,
-    pub witness: Option<Vec<u8>>,
+    /// Witness stack items of the input (BIP141)
+    pub witness: Option<Vec<Vec<u8>>>,
 }
 
 impl TransactionInput {
-    pub fn with_witness(&mut self, witness: Vec<u8>) -> () {
+    pub fn with_witness(&mut self, witness: Vec<Vec<u8>>) -> () {
         self.witness = Some(witness);
     }
+
+    /// Checks if the input spends the output at `index` of the
+    /// transaction `txid`. Coinbase inputs spend no output.
+    pub fn spends(&self, txid: &H256Le, index: u32) -> bool {
+        !self.coinbase && self.previous_hash == *txid && self.previous_index == index
+    }
 }
 
 /// Bitcoin transaction output
@@ -79,7 +167,7 @@ pub struct TransactionOutput {
 }
 
 /// Bitcoin transaction
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub struct Transaction {
     pub version: i32,
     pub inputs: Vec<TransactionInput>,
@@ -93,6 +181,52 @@ impl Transaction {
     pub fn tx_id(raw_tx: &[u8]) -> H256Le {
         sha256d_le(&raw_tx)
     }
+
+    /// Serializes the transaction without witness data, i.e. the
+    /// format used to compute the transaction id
+    pub fn to_legacy_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&self.version.to_le_bytes());
+
+        bytes.extend(CompactUint::from(self.inputs.len()).to_bytes());
+        for input in &self.inputs {
+            bytes.extend_from_slice(&input.previous_hash.to_bytes_le());
+            bytes.extend_from_slice(&input.previous_index.to_le_bytes());
+            // the height of version 2 coinbase inputs is part of the script
+            let height = input.height.clone().unwrap_or_default();
+            bytes.extend(CompactUint::from(height.len() + input.script.len()).to_bytes());
+            bytes.extend(height);
+            bytes.extend_from_slice(&input.script);
+            bytes.extend_from_slice(&input.sequence.to_le_bytes());
+        }
+
+        bytes.extend(CompactUint::from(self.outputs.len()).to_bytes());
+        for output in &self.outputs {
+            bytes.extend_from_slice(&output.value.to_le_bytes());
+            bytes.extend(CompactUint::from(output.script.len()).to_bytes());
+            bytes.extend_from_slice(&output.script);
+        }
+
+        let locktime = self.locktime.or(self.block_height).unwrap_or(0);
+        bytes.extend_from_slice(&locktime.to_le_bytes());
+        bytes
+    }
+
+    /// Checks if the transaction can be included in a block at the given
+    /// height and median time past (Bitcoin's IsFinalTx). The locktime is
+    /// interpreted as a block height below 500,000,000 and as a unix
+    /// timestamp otherwise, and is ignored if all inputs are final.
+    /// # Arguments
+    ///  * `block_height`: height of the block including the transaction
+    ///  * `block_time`: median time past of the preceding block
+    pub fn is_final(&self, block_height: u32, block_time: u64) -> bool {
+        let lock_satisfied = match (self.block_height, self.locktime) {
+            (Some(height), _) => height == 0 || height < block_height,
+            (None, Some(time)) => (time as u64) < block_time,
+            (None, None) => true,
+        };
+        lock_satisfied || self.inputs.iter().all(|input| input.sequence == u32::max_value())
+    }
 }
 
 /// Bitcoin Enriched Block Headers
@@ -128,6 +262,62 @@ pub struct BlockChain {
     pub max_height: u32,
     pub no_data: BTreeSet<u32>,
     pub invalid: BTreeSet<u32>,
+    /// Cumulative work of the chain up to and including its tip,
+    /// counted from the first block stored in the relay
+    pub total_work: U256,
+}
+
+/// Summary of the relay state, used as a health probe
+#[derive(Encode, Decode, Default, Clone, PartialEq, Debug)]
+pub struct RelayStatus {
+    pub initialized: bool,
+    pub best_height: u32,
+    pub best_hash: H256Le,
+    pub best_time: Moment,
+    pub chain_count: u32,
+    pub fork_count: u32,
+    pub main_chain_work: U256,
+    pub last_reorg_depth: u32,
+    pub blocks_behind: u32,
+}
+
+/// Outcome of a dry run of the transaction inclusion check
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InclusionVerification {
+    /// The merkle proof verifies against the main chain
+    Verified,
+    /// The check failed with the given BTC-Relay error code
+    Rejected(u8),
+}
+
+/// Recipient of a Bitcoin payment, i.e. the hash or witness program an
+/// output script pays to
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Recipient {
+    /// 20 byte address hash (P2PKH, P2SH) or witness program (P2WPKH)
+    H160(H160),
+    /// 32 byte witness program (P2WSH)
+    H256(H256),
+}
+
+impl Recipient {
+    /// Returns the recipient of a 20 byte address hash or a 32 byte
+    /// witness program, `None` for any other length
+    pub fn from_slice(bytes: &[u8]) -> Option<Recipient> {
+        match bytes.len() {
+            P2WPKH_PROGRAM_SIZE => Some(Recipient::H160(H160::from_slice(bytes))),
+            P2WSH_PROGRAM_SIZE => Some(Recipient::H256(H256::from_slice(bytes))),
+            _ => None,
+        }
+    }
+
+    /// Returns the address hash or witness program
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Recipient::H160(hash) => hash.as_bytes(),
+            Recipient::H256(program) => program.as_bytes(),
+        }
+    }
 }
 
 /// Represents a bitcoin 32 bytes hash digest encoded in little-endian
@@ -158,12 +348,44 @@ impl H256Le {
         H256Le { content: content }
     }
 
+    /// Creates a H256Le from little endian hex, optionally prefixed
+    /// with `0x`
+    ///
+    /// # Panics
+    ///
+    /// If the string is not valid hex of 32 bytes, see `try_from_hex_le`
     pub fn from_hex_le(hex: &str) -> H256Le {
-        H256Le::from_bytes_le(&bitcoin_spv::utils::deserialize_hex(hex).unwrap())
+        H256Le::try_from_hex_le(hex).unwrap()
     }
 
+    /// Creates a H256Le from big endian hex, optionally prefixed with `0x`
+    ///
+    /// # Panics
+    ///
+    /// If the string is not valid hex of 32 bytes, see `try_from_hex_be`
     pub fn from_hex_be(hex: &str) -> H256Le {
-        H256Le::from_bytes_be(&bitcoin_spv::utils::deserialize_hex(hex).unwrap())
+        H256Le::try_from_hex_be(hex).unwrap()
+    }
+
+    /// Creates a H256Le from little endian hex, optionally prefixed
+    /// with `0x`, or fails with `MalformedHex`
+    pub fn try_from_hex_le(hex: &str) -> Result<H256Le, Error> {
+        Ok(H256Le::from_bytes_le(&H256Le::decode_hex(hex)?))
+    }
+
+    /// Creates a H256Le from big endian hex, optionally prefixed with
+    /// `0x`, or fails with `MalformedHex`
+    pub fn try_from_hex_be(hex: &str) -> Result<H256Le, Error> {
+        Ok(H256Le::from_bytes_be(&H256Le::decode_hex(hex)?))
+    }
+
+    /// Decodes hex of exactly 32 bytes
+    fn decode_hex(hex: &str) -> Result<Vec<u8>, Error> {
+        let bytes = bitcoin_spv::utils::deserialize_hex(hex).map_err(|_e| Error::MalformedHex)?;
+        if bytes.len() != 32 {
+            return Err(Error::MalformedHex);
+        }
+        Ok(bytes)
     }
 
     /// Returns the content of the H256Le encoded in big endian
@@ -178,7 +400,8 @@ impl H256Le {
         self.content.clone()
     }
 
-    /// Returns the content of the H256Le encoded in little endian hex
+    /// Returns the content of the H256Le encoded in little endian hex,
+    /// prefixed with `0x`
     pub fn to_hex_le(&self) -> String {
         bitcoin_spv::utils::serialize_hex(&self.to_bytes_le())
     }
@@ -237,7 +460,19 @@ pub enum Error {
     MalformedOpReturnOutput,
 
     // Output does not match format of supported output types (Witness, P2PKH, P2SH)
-    UnsupportedOutputFormat
+    UnsupportedOutputFormat,
+
+    /// Hex string is not valid hex of the expected length
+    MalformedHex,
+
+    /// Compact nBits target is negative or exceeds 256 bits
+    MalformedBits,
+
+    /// Format of the block is invalid or it lists a transaction twice
+    MalformedBlock,
+
+    /// Transactions of the block do not yield the merkle root of its header
+    InvalidMerkleRoot
 }
 
 
@@ -252,7 +487,11 @@ impl std::fmt::Display for Error {
             Error::MalformedP2PKHOutput => write!(f, "invalid P2PKH output format"),
             Error::MalformedP2SHOutput => write!(f, "invalid P2SH output format"),
             Error::MalformedOpReturnOutput => write!(f, "invalid OP_RETURN output format"),
-            Error::UnsupportedOutputFormat => write!(f, "unsupported output type. Currently supported: Witness, P2PKH, P2SH")
+            Error::UnsupportedOutputFormat => write!(f, "unsupported output type. Currently supported: Witness, P2PKH, P2SH"),
+            Error::MalformedHex => write!(f, "invalid hex format"),
+            Error::MalformedBits => write!(f, "invalid compact target"),
+            Error::MalformedBlock => write!(f, "invalid block format"),
+            Error::InvalidMerkleRoot => write!(f, "transactions do not match the merkle root")
         }
     }
 }
@@ -264,7 +503,8 @@ pub enum OpCode {
     OpEqualVerify = 0x88,
     OpCheckSig = 0xac, 
     OpEqual = 0x87,
-    OpReturn = 0x6a
+    OpReturn = 0x6a,
+    OpPushData1 = 0x4c
 }
 
 impl PartialEq<H256Le> for H256 {
@@ -285,6 +525,36 @@ pub(crate) struct CompactUint {
     pub(crate) value: u64,
 }
 
+impl From<usize> for CompactUint {
+    fn from(value: usize) -> CompactUint {
+        CompactUint { value: value as u64 }
+    }
+}
+
+impl CompactUint {
+    /// Encodes the value using the smallest representation
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        match self.value {
+            0..=0xfc => vec![self.value as u8],
+            0xfd..=0xffff => {
+                let mut bytes = vec![0xfd];
+                bytes.extend_from_slice(&(self.value as u16).to_le_bytes());
+                bytes
+            },
+            0x10000..=0xffffffff => {
+                let mut bytes = vec![0xfe];
+                bytes.extend_from_slice(&(self.value as u32).to_le_bytes());
+                bytes
+            },
+            _ => {
+                let mut bytes = vec![0xff];
+                bytes.extend_from_slice(&self.value.to_le_bytes());
+                bytes
+            },
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -303,6 +573,175 @@ mod tests {
         assert_eq!(content_be.to_bytes_be(), bytes);
     }
 
+    #[test]
+    fn test_h256_hex() {
+        let hex_le = "7b1eabe0209b1fe794124575ef807057c77ada2138ae4fa8d6c4de0398a14f3f";
+        let hex_be = "3f4fa19803dec4d6a84fae3821da7ac7577080ef75451294e71f9b20e0ab1e7b";
+
+        let hash = H256Le::from_hex_le(hex_le);
+        assert_eq!(hash, H256Le::from_hex_be(hex_be));
+        assert_eq!(hash, H256Le::from_hex_le(&("0x".to_owned() + hex_le)));
+        // little endian hex keeps the 0x prefix of serialize_hex
+        assert_eq!(hash.to_hex_le(), "0x".to_owned() + hex_le);
+        assert_eq!(hash.to_hex_be(), hex_be);
+
+        assert_eq!(H256Le::from_hex_le(&hash.to_hex_le()), hash);
+        assert_eq!(H256Le::from_hex_be(&hash.to_hex_be()), hash);
+    }
+
+    #[test]
+    fn test_h256_malformed_hex() {
+        // odd length
+        assert_eq!(H256Le::try_from_hex_le("7b1"), Err(Error::MalformedHex));
+        assert_eq!(H256Le::try_from_hex_be("7b1"), Err(Error::MalformedHex));
+        // not hex
+        assert_eq!(H256Le::try_from_hex_le(&"zz".repeat(32)), Err(Error::MalformedHex));
+        // not 32 bytes
+        assert_eq!(H256Le::try_from_hex_le(&"7b".repeat(31)), Err(Error::MalformedHex));
+        assert_eq!(H256Le::try_from_hex_be(&"7b".repeat(33)), Err(Error::MalformedHex));
+    }
+
+    #[test]
+    fn test_recipient_from_slice() {
+        assert_eq!(Recipient::from_slice(&[1; 20]), Some(Recipient::H160(H160::repeat_byte(1))));
+        assert_eq!(Recipient::from_slice(&[1; 32]), Some(Recipient::H256(H256::repeat_byte(1))));
+        assert_eq!(Recipient::from_slice(&[1; 32]).unwrap().as_bytes(), &[1; 32]);
+        // neither an address hash nor a witness program
+        assert_eq!(Recipient::from_slice(&[1; 19]), None);
+        assert_eq!(Recipient::from_slice(&[1; 21]), None);
+    }
+
+    #[test]
+    fn test_compact_uint_to_bytes() {
+        assert_eq!(CompactUint { value: 0xfc }.to_bytes(), vec![0xfc]);
+        assert_eq!(CompactUint { value: 0xfd }.to_bytes(), vec![0xfd, 0xfd, 0x00]);
+        assert_eq!(CompactUint { value: 0x10000 }.to_bytes(), vec![0xfe, 0x00, 0x00, 0x01, 0x00]);
+        assert_eq!(
+            CompactUint { value: 0x100000000 }.to_bytes(),
+            vec![0xff, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn test_block_header_bits() {
+        // block 68544
+        let raw_header = header_from_bytes(&hex::decode("01000000".to_owned() + "fb57c71ccd211b3de4ccc2e23b50a7cdb72aab91e60737b3a2bfdf030000000088a88ad9df68925e880e5d52b7e50cef225871c68b40a2cd0bca1084cd436037f388404cfd68011caeb1f801").unwrap());
+        let header = parse_block_header(raw_header);
+        assert_eq!(header.bits, 0x1c0168fd);
+        assert_eq!(nbits_from_target(header.target), 0x1c0168fd);
+        assert!(header.has_canonical_bits());
+
+        // block 560448
+        let raw_header = header_from_bytes(&hex::decode("00000020".to_owned() + "6b05bd2c4a06b3d8503a033c2593396a25a79e1dcadb140000000000000000001b08df3d42cd9a38d8b66adf9dc5eb464f503633bd861085ffff723634531596a1a24e5c35683017bf67b72a").unwrap());
+        let header = parse_block_header(raw_header);
+        assert_eq!(header.bits, 0x17306835);
+        assert!(header.has_canonical_bits());
+
+        // minimum difficulty
+        assert_eq!(nbits_from_target(U256::from(0xffff) << 208), 0x1d00ffff);
+    }
+
+    #[test]
+    fn test_target_from_nbits() {
+        // block 0
+        assert_eq!(target_from_nbits(0x1d00ffff), Ok(U256::from(0xffff) << 208));
+        // block 68544
+        assert_eq!(target_from_nbits(0x1c0168fd), Ok(U256::from(0x0168fd) << 200));
+        // block 328014
+        assert_eq!(target_from_nbits(0x181bc330), Ok(U256::from(0x1bc330) << 168));
+        // block 560448
+        assert_eq!(target_from_nbits(0x17306835), Ok(U256::from(0x306835) << 160));
+
+        // small exponents shift the mantissa to the right
+        assert_eq!(target_from_nbits(0x00123456), Ok(U256::zero()));
+        assert_eq!(target_from_nbits(0x01123456), Ok(U256::from(0x12)));
+        assert_eq!(target_from_nbits(0x02008000), Ok(U256::from(0x80)));
+        // a zero mantissa is never negative or overflowing
+        assert_eq!(target_from_nbits(0xff000000), Ok(U256::zero()));
+
+        for nbits in [0x1d00ffffu32, 0x1c0168fd, 0x181bc330, 0x17306835, 0x02008000].iter() {
+            assert_eq!(nbits_from_target(target_from_nbits(*nbits).unwrap()), *nbits);
+        }
+    }
+
+    #[test]
+    fn test_target_from_nbits_malformed_fails() {
+        // sign bit set
+        assert_eq!(target_from_nbits(0x01fedcba), Err(Error::MalformedBits));
+        assert_eq!(target_from_nbits(0x04923456), Err(Error::MalformedBits));
+        // exceeds 256 bits
+        assert_eq!(target_from_nbits(0xff123456), Err(Error::MalformedBits));
+        assert_eq!(target_from_nbits(0x21010000), Err(Error::MalformedBits));
+    }
+
+    #[test]
+    fn test_block_header_non_canonical_bits() {
+        // 0x1d000168 encodes the same target as the canonical 0x1c016800
+        let header = BlockHeader {
+            target: U256::from(0x0168) << 208,
+            bits: 0x1d000168,
+            ..Default::default()
+        };
+        assert_eq!(nbits_from_target(header.target), 0x1c016800);
+        assert!(!header.has_canonical_bits());
+
+        // the sign bit of the mantissa is set
+        let header = BlockHeader {
+            target: U256::from(0x016800) << 200,
+            bits: 0x1c816800,
+            ..Default::default()
+        };
+        assert!(!header.has_canonical_bits());
+    }
+
+    fn sample_locked_transaction(block_height: Option<u32>, locktime: Option<u32>, sequence: u32) -> Transaction {
+        Transaction {
+            version: 2,
+            inputs: vec![TransactionInput {
+                previous_hash: H256Le::zero(),
+                previous_index: 0,
+                coinbase: false,
+                height: None,
+                script: vec![],
+                sequence: sequence,
+                witness: None,
+            }],
+            outputs: vec![],
+            block_height: block_height,
+            locktime: locktime,
+        }
+    }
+
+    #[test]
+    fn test_transaction_is_final() {
+        // height locktime
+        assert!(sample_locked_transaction(Some(0), None, 0).is_final(10, 0));
+        assert!(sample_locked_transaction(Some(9), None, 0).is_final(10, 0));
+        assert!(!sample_locked_transaction(Some(10), None, 0).is_final(10, 0));
+
+        // time locktime
+        assert!(sample_locked_transaction(None, Some(500_000_000), 0).is_final(10, 500_000_001));
+        assert!(!sample_locked_transaction(None, Some(500_000_000), 0).is_final(10, 500_000_000));
+
+        // final inputs disable the locktime
+        assert!(sample_locked_transaction(Some(10), None, u32::max_value()).is_final(10, 0));
+    }
+
+    #[test]
+    fn test_transaction_input_spends() {
+        let txid = H256Le::from_bytes_le(&[1; 32]);
+        let mut input = sample_locked_transaction(None, None, 0).inputs.remove(0);
+        input.previous_hash = txid;
+        input.previous_index = 1;
+
+        assert!(input.spends(&txid, 1));
+        assert!(!input.spends(&txid, 0));
+        assert!(!input.spends(&H256Le::zero(), 1));
+
+        input.coinbase = true;
+        assert!(!input.spends(&txid, 1));
+    }
+
     #[test]
     fn test_partial_eq() {
         let mut bytes: [u8; 32] = [0; 32];
@@ -32,6 +32,24 @@ pub enum Error {
     ChainCounterOverflow, // not in spec
     BlockHeightOverflow, // not in spec
     ChainsUnderflow, // not in spec
+    BlockNotInMainChain, // not in spec
+    Bip34HeightMismatch, // not in spec
+    TimestampTooOld, // not in spec
+    TimestampTooFarAhead, // not in spec
+    BlockNotFlagged, // not in spec
+    InvalidBlock, // not in spec
+    NoDataBlock, // not in spec
+    BlockHeightNotContiguous, // not in spec
+    MalformedAddress, // not in spec
+    TxNotFinal, // not in spec
+    /// The previous block is the tip of one of the last MAX_PRUNED_FORKS
+    /// forks pruned by BTC-Relay. Children of other pruned headers fail
+    /// with PrevBlock.
+    BlockBelowPruningWindow, // not in spec
+    NotInitialized, // not in spec
+    ArithmeticOverflow, // not in spec
+    OutputNotSpent, // not in spec
+    InvalidWitnessCommitment, // not in spec
 }
 
 impl Error {
@@ -67,6 +85,21 @@ impl Error {
             Error::ChainCounterOverflow => "Overflow of chain counter", 
             Error::BlockHeightOverflow => "Overflow of block height", 
             Error::ChainsUnderflow => "Underflow of stored blockchains counter", 
+            Error::BlockNotInMainChain => "Block is not part of the main chain",
+            Error::Bip34HeightMismatch => "Coinbase BIP34 height does not match the block height",
+            Error::TimestampTooOld => "Block timestamp is not greater than the median time past",
+            Error::TimestampTooFarAhead => "Block timestamp is too far in the future",
+            Error::BlockNotFlagged => "Block is not flagged with this error code",
+            Error::InvalidBlock => "Block is flagged as invalid",
+            Error::NoDataBlock => "Block is flagged as missing transaction data",
+            Error::BlockHeightNotContiguous => "Block height does not directly follow the chain tip",
+            Error::MalformedAddress => "Recipient address is neither a 20 byte hash nor a 32 byte witness program",
+            Error::TxNotFinal => "Transaction locktime is not satisfied at the next block",
+            Error::BlockBelowPruningWindow => "Previous block is the tip of a recently pruned stale fork",
+            Error::NotInitialized => "BTC-Relay has not been initialized",
+            Error::ArithmeticOverflow => "Arithmetic overflow",
+            Error::OutputNotSpent => "Transaction does not spend the given output",
+            Error::InvalidWitnessCommitment => "Coinbase does not commit to the witness merkle root",
         }
     }
 }
@@ -0,0 +1,101 @@
+//! Runtime API definition for the BTC-Relay module.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+pub use bitcoin::types::{
+    BitcoinNetwork, H256Le, InclusionVerification, RelayStatus, RichBlockHeader,
+};
+pub use primitive_types::U256;
+use sp_std::vec::Vec;
+
+sp_api::decl_runtime_apis! {
+    /// Read-only queries against the BTC-Relay state
+    pub trait BTCRelayApi {
+        /// Returns the hash of the main chain tip
+        fn best_block_hash() -> H256Le;
+
+        /// Returns the height of the main chain tip
+        fn best_block_height() -> u32;
+
+        /// Checks if the relay was initialized, i.e. if block headers can
+        /// be submitted
+        fn is_fully_initialized() -> bool;
+
+        /// Returns the height and hash of the tip of a tracked chain
+        fn chain_tip_for(chain_id: u32) -> Option<(u32, H256Le)>;
+
+        /// Checks if the output script pays to the given recipient
+        fn script_matches_recipient(
+            script: Vec<u8>,
+            recipient: Vec<u8>,
+            network: BitcoinNetwork,
+        ) -> bool;
+
+        /// Returns the depth of a main chain block below the tip, or `None`
+        /// if the block is unknown or not part of the main chain
+        fn block_depth(block_hash: H256Le) -> Option<u32>;
+
+        /// Returns the number of main chain blocks on top of a block, or
+        /// `None` if the block is unknown or not part of the main chain
+        fn blocks_behind_tip(block_hash: H256Le) -> Option<u32>;
+
+        /// Checks if a block is the tip of the main chain
+        fn is_block_main_chain_tip(block_hash: H256Le) -> bool;
+
+        /// Returns the id of the chain a block is stored on, or `None` if
+        /// the block is unknown
+        fn get_chain_ref_for_block(block_hash: H256Le) -> Option<u32>;
+
+        /// Checks if a block is part of the main chain
+        fn is_block_canonical(block_hash: H256Le) -> bool;
+
+        /// Returns the compact nBits target of the main chain block at the
+        /// given height
+        fn get_bits_at_height(block_height: u32) -> Option<u32>;
+
+        /// Returns a page of the block headers at the given height of all
+        /// tracked chains, skipping `from` and returning at most `limit`
+        /// (bounded by MAX_PAGE_SIZE) headers
+        fn headers_at_height(block_height: u32, from: u32, limit: u32) -> Vec<RichBlockHeader>;
+
+        /// Returns a page of the block headers of a chain in height order,
+        /// skipping `from` and returning at most `limit` (bounded by
+        /// MAX_PAGE_SIZE) headers
+        fn dump_chain(chain_id: u32, from: u32, limit: u32) -> Vec<RichBlockHeader>;
+
+        /// Returns the target the next main chain block must use, or `None`
+        /// if the relay is not initialized
+        fn next_block_target() -> Option<U256>;
+
+        /// Returns a summary of the relay state, given the current time in
+        /// seconds since the unix epoch
+        fn relay_status(current_time: u64) -> RelayStatus;
+
+        /// Returns the lead of a fork over the main chain in height and in
+        /// work (clamped at zero), or `None` if the fork is not tracked
+        fn fork_lead(chain_id: u32) -> Option<(i64, U256)>;
+
+        /// Returns the hash of the main chain block at the given height, or
+        /// `None` if the height is outside the stored main chain
+        fn get_main_chain_block_hash(block_height: u32) -> Option<H256Le>;
+
+        /// Returns the heights and hashes of up to `count` (bounded by
+        /// MAX_PAGE_SIZE) consecutive main chain blocks starting at
+        /// `from_height`, clamped to the stored main chain
+        fn get_main_chain_hashes(from_height: u32, count: u32) -> Vec<(u32, H256Le)>;
+
+        /// Returns all tracked forks as `(position, chain_id, max_height)`,
+        /// sorted by position
+        fn get_ongoing_forks() -> Vec<(u32, u32, u32)>;
+
+        /// Checks the inclusion of a transaction like the
+        /// verify_transaction_inclusion extrinsic, without submitting it
+        fn verify_transaction_inclusion(
+            tx_id: H256Le,
+            block_height: u32,
+            raw_merkle_proof: Vec<u8>,
+            confirmations: u32,
+            insecure: bool,
+        ) -> InclusionVerification;
+    }
+}
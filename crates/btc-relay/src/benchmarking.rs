@@ -0,0 +1,231 @@
+//! Benchmarks of the BTC-Relay dispatchables
+//!
+//! The headers are mined against the regtest target so that the
+//! proof-of-work and re-target checks run as on any other network.
+
+use super::*;
+use bitcoin::merkle::compute_root_from_path;
+use frame_benchmarking::{account, benchmarks};
+use sp_core::H160;
+use sp_std::prelude::*;
+use system::RawOrigin;
+
+/// Compact target of the Bitcoin regtest network, met by about every
+/// second block hash
+const REGTEST_BITS: u32 = 0x207fffff;
+
+/// Returns a raw block header on top of `prev_hash` meeting REGTEST_BITS
+///
+/// # Arguments
+///
+/// * `prev_hash` - hash of the parent block
+/// * `merkle_root` - merkle root, used to tell apart blocks of forks
+/// * `timestamp` - timestamp of the block
+fn mine_header(prev_hash: H256Le, merkle_root: H256Le, timestamp: u32) -> RawBlockHeader {
+    let mut raw_header: RawBlockHeader = [0; 80];
+    raw_header[0..4].copy_from_slice(&2u32.to_le_bytes());
+    raw_header[4..36].copy_from_slice(&prev_hash.to_bytes_le());
+    raw_header[36..68].copy_from_slice(&merkle_root.to_bytes_le());
+    raw_header[68..72].copy_from_slice(&timestamp.to_le_bytes());
+    raw_header[72..76].copy_from_slice(&REGTEST_BITS.to_le_bytes());
+
+    let target = target_from_nbits(REGTEST_BITS).unwrap_or_default();
+    let mut nonce: u32 = 0;
+    loop {
+        raw_header[76..80].copy_from_slice(&nonce.to_le_bytes());
+        if BlockHeader::block_hash_le(&raw_header).as_u256() < target {
+            return raw_header;
+        }
+        nonce += 1;
+    }
+}
+
+/// Returns a raw transaction with a single input and the given outputs
+///
+/// # Arguments
+///
+/// * `outputs` - pairs of value and script of the outputs, fewer than 253
+fn raw_transaction(outputs: &[(i64, Vec<u8>)]) -> Vec<u8> {
+    let mut raw_tx = Vec::new();
+    raw_tx.extend_from_slice(&2u32.to_le_bytes());
+    // a single input without script
+    raw_tx.push(1);
+    raw_tx.extend_from_slice(&[0; 36]);
+    raw_tx.push(0);
+    raw_tx.extend_from_slice(&u32::max_value().to_le_bytes());
+
+    raw_tx.push(outputs.len() as u8);
+    for (value, script) in outputs.iter() {
+        raw_tx.extend_from_slice(&value.to_le_bytes());
+        raw_tx.push(script.len() as u8);
+        raw_tx.extend_from_slice(script);
+    }
+    raw_tx.extend_from_slice(&0u32.to_le_bytes());
+    raw_tx
+}
+
+/// Initializes the relay at height 0 and returns the hash of the first block
+fn initialize_chain<T: Trait>() -> H256Le {
+    let raw_header = mine_header(H256Le::zero(), H256Le::zero(), 1);
+    Module::<T>::initialize_relay(&raw_header, 0).unwrap()
+}
+
+/// Stores `length` blocks on top of the given block and returns their hashes
+///
+/// # Arguments
+///
+/// * `prev_hash` - hash of the block to build on
+/// * `prev_height` - height of the block to build on
+/// * `length` - number of blocks to store
+/// * `merkle_root` - merkle root of the stored blocks
+fn extend_chain<T: Trait>(
+    prev_hash: H256Le,
+    prev_height: u32,
+    length: u32,
+    merkle_root: H256Le,
+) -> Vec<H256Le> {
+    let mut hashes = Vec::new();
+    let mut prev_hash = prev_hash;
+    for height in prev_height + 1..=prev_height + length {
+        let raw_header = mine_header(prev_hash, merkle_root, height + 1);
+        Module::<T>::do_store_block_header(&raw_header).unwrap();
+        prev_hash = BlockHeader::block_hash_le(&raw_header);
+        hashes.push(prev_hash);
+    }
+    hashes
+}
+
+benchmarks! {
+    _ { }
+
+    initialize {
+        let caller = account("caller", 0, 0);
+        let raw_header = mine_header(H256Le::zero(), H256Le::zero(), 1);
+    }: _(RawOrigin::Signed(caller), raw_header.to_vec(), 0)
+
+    store_block_header_on_main_chain {
+        let caller = account("caller", 0, 0);
+        let genesis = initialize_chain::<T>();
+        let main_chain = extend_chain::<T>(genesis, 0, 10, H256Le::zero());
+        let raw_header = mine_header(main_chain[9], H256Le::zero(), 12);
+    }: store_block_header(RawOrigin::Signed(caller), raw_header.to_vec())
+
+    store_block_header_new_fork {
+        let caller = account("caller", 0, 0);
+        let genesis = initialize_chain::<T>();
+        let main_chain = extend_chain::<T>(genesis, 0, 10, H256Le::zero());
+        // a child of the block at height 5
+        let raw_header = mine_header(main_chain[4], H256Le::from_bytes_le(&[1; 32]), 7);
+    }: store_block_header(RawOrigin::Signed(caller), raw_header.to_vec())
+
+    store_block_header_reorg {
+        // number of main chain blocks moved to a fork
+        let d in 1 .. 50;
+        let caller = account("caller", 0, 0);
+        let genesis = initialize_chain::<T>();
+        extend_chain::<T>(genesis, 0, d, H256Le::zero());
+
+        // the fork needs more than the stable confirmations worth of work
+        // over the main chain, the submitted block completes it
+        let fork_root = H256Le::from_bytes_le(&[1; 32]);
+        let fork_length = d + Module::<T>::get_stable_transaction_confirmations();
+        let fork = extend_chain::<T>(genesis, 0, fork_length, fork_root);
+        let raw_header = mine_header(fork[fork.len() - 1], fork_root, fork_length + 2);
+    }: store_block_header(RawOrigin::Signed(caller), raw_header.to_vec())
+
+    verify_transaction_inclusion {
+        // height of the merkle tree, the proof holds h + 1 hashes
+        let h in 1 .. 14;
+        let caller = account("caller", 0, 0);
+
+        // the transaction is the first of a block with 2^h transactions
+        let tx_id = H256Le::from_bytes_le(&[2; 32]);
+        let siblings: Vec<H256Le> = (0..h)
+            .map(|level| H256Le::from_bytes_le(&[level as u8 + 3; 32]))
+            .collect();
+        let merkle_root = compute_root_from_path(tx_id, 0, &siblings).unwrap();
+
+        let genesis = initialize_chain::<T>();
+        let raw_header = mine_header(genesis, merkle_root, 2);
+        Module::<T>::do_store_block_header(&raw_header).unwrap();
+
+        let mut raw_merkle_proof = raw_header.to_vec();
+        raw_merkle_proof.extend_from_slice(&(1u32 << h).to_le_bytes());
+        raw_merkle_proof.push(h as u8 + 1);
+        raw_merkle_proof.extend_from_slice(&tx_id.to_bytes_le());
+        for sibling in siblings.iter() {
+            raw_merkle_proof.extend_from_slice(&sibling.to_bytes_le());
+        }
+        // the path to the transaction is traversed first (flag bits set),
+        // then the sibling of each node on the path (flag bits unset)
+        let flag_bits_count = 2 * h + 1;
+        let mut flag_bytes = vec![0u8; ((flag_bits_count + 7) / 8) as usize];
+        for bit in 0..=h {
+            flag_bytes[(bit / 8) as usize] |= 1 << (bit % 8);
+        }
+        raw_merkle_proof.push(flag_bytes.len() as u8);
+        raw_merkle_proof.extend(flag_bytes);
+    }: _(RawOrigin::Signed(caller), tx_id, 1, raw_merkle_proof, 1, true)
+
+    validate_transaction {
+        // number of outputs besides the payment and the OP_RETURN output
+        let c in 0 .. 200;
+        let caller = account("caller", 0, 0);
+        let op_return_id = vec![1u8; 32];
+        let recipient = Recipient::H160(H160::repeat_byte(1));
+
+        let mut payment_script = vec![0x00, 0x14];
+        payment_script.extend_from_slice(recipient.as_bytes());
+        let mut outputs = vec![(100_000, payment_script)];
+        for _ in 0..c {
+            let mut script = vec![0x00, 0x20];
+            script.extend_from_slice(&[0xff; 32]);
+            outputs.push((100_000, script));
+        }
+        let mut data_script = vec![OpCode::OpReturn as u8, 0x20];
+        data_script.extend_from_slice(&op_return_id);
+        outputs.push((0, data_script));
+        let raw_tx = raw_transaction(&outputs);
+    }: _(RawOrigin::Signed(caller), raw_tx, 100_000, recipient, op_return_id, true)
+
+    validate_transaction_multi {
+        // number of expected payments, each paid by its own output
+        let p in 1 .. 100;
+        // number of other outputs, e.g. change
+        let c in 0 .. 100;
+        let caller = account("caller", 0, 0);
+        let op_return_id = vec![1u8; 32];
+
+        let expected_payments: Vec<(Recipient, i64)> = (0..p)
+            .map(|index| (Recipient::H160(H160::repeat_byte(index as u8)), 100_000))
+            .collect();
+        let mut outputs: Vec<(i64, Vec<u8>)> = expected_payments
+            .iter()
+            .map(|(recipient, value)| {
+                let mut script = vec![0x00, 0x14];
+                script.extend_from_slice(recipient.as_bytes());
+                (*value, script)
+            })
+            .collect();
+        for _ in 0..c {
+            let mut script = vec![0x00, 0x20];
+            script.extend_from_slice(&[0xff; 32]);
+            outputs.push((100_000, script));
+        }
+        let mut data_script = vec![OpCode::OpReturn as u8, 0x20];
+        data_script.extend_from_slice(&op_return_id);
+        outputs.push((0, data_script));
+        let raw_tx = raw_transaction(&outputs);
+    }: _(RawOrigin::Signed(caller), raw_tx, expected_payments, op_return_id)
+
+    prune_stale_headers {
+        // number of headers of the stale fork
+        let h in 1 .. MAX_PRUNED_HEADERS;
+        let genesis = initialize_chain::<T>();
+        extend_chain::<T>(genesis, 0, h + 1, H256Le::zero());
+        extend_chain::<T>(genesis, 0, h, H256Le::from_bytes_le(&[1; 32]));
+
+        // move the main chain tip past the pruning window of the fork
+        Module::<T>::set_best_block_height(h + 1 + Module::<T>::get_pruning_window());
+    }: _(RawOrigin::Root, h)
+}
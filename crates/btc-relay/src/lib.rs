@@ -6,6 +6,9 @@ mod tests;
 #[cfg(test)]
 mod mock;
 
+#[cfg(feature = "runtime-benchmarks")]
+mod benchmarking;
+
 #[cfg(test)]
 extern crate mocktopus;
 
@@ -17,37 +20,262 @@ use mocktopus::macros::mockable;
 /// https://interlay.gitlab.io/polkabtc-spec/btcrelay-spec/
 
 // Substrate
+use codec::{Decode, Encode};
 use frame_support::{
-    decl_event, decl_module, decl_storage, dispatch::DispatchResult, ensure
+    decl_event, decl_module, decl_storage, dispatch::DispatchResult, ensure,
+    traits::Get,
+    weights::{DispatchClass, FunctionOf, SimpleDispatchInfo, Weight},
 };
-use sp_core::{H160, U256};
+use node_primitives::Moment;
+use sp_core::U256;
+use sp_runtime::Perbill;
+use sp_runtime::traits::SaturatedConversion;
 use sp_std::collections::btree_map::BTreeMap;
 use sp_std::collections::btree_set::BTreeSet;
-use system::ensure_signed;
+use system::{ensure_root, ensure_signed};
 
 // Crates
-use bitcoin::merkle::{MerkleProof, ProofResult};
+use bitcoin::merkle::{verify_block_merkle_root, MerkleProof, ProofResult};
 use bitcoin::parser::{
-    extract_address_hash, extract_op_return_data, 
-    header_from_bytes, parse_block_header, parse_transaction,
+    extract_address_hash, extract_coinbase_height, extract_op_return_data, extract_tx_id,
+    header_from_bytes, parse_block_header, parse_transaction, parse_transaction_outputs,
 };
 use bitcoin::types::{
-    BlockChain, BlockHeader, H256Le, 
-    RawBlockHeader, RichBlockHeader, Transaction
+    BitcoinNetwork, BlockChain, BlockHeader, H256Le, OpCode, Recipient,
+    RawBlockHeader, RelayStatus, RichBlockHeader, Transaction, TransactionOutput,
+    nbits_from_target, target_from_nbits,
 };
+use bitcoin::utils::sha256d;
 use security;
-use security::ErrorCode;
+use security::{ErrorCode, StatusCode};
 
-use btc_core::Error;
+pub use btc_core::Error;
 
 /// ## Configuration and Constants
 /// The pallet's configuration trait.
 /// For further reference, see:
 /// https://interlay.gitlab.io/polkabtc-spec/btcrelay-spec/spec/data-model.html
-pub trait Trait: system::Trait //+ security::Trait
+pub trait Trait: system::Trait + timestamp::Trait //+ security::Trait
 {
     /// The overarching event type.
     type Event: From<Event> + Into<<Self as system::Trait>::Event>;
+
+    /// Handler notified when a reorg removes blocks from the main chain
+    type OnReorg: OnReorgHandler;
+
+    /// Main chain block headers still referenced by dependent pallets,
+    /// which are kept when pruning
+    type PruningFilter: MainChainPruningFilter;
+
+    /// Upper bound of the retarget timespan as a multiple of
+    /// TARGET_TIMESPAN (4 in Bitcoin)
+    type RetargetMaxFactor: Get<u32>;
+
+    /// Lower bound of the retarget timespan as a fraction of
+    /// TARGET_TIMESPAN (1/4 in Bitcoin)
+    type RetargetMinFactor: Get<Perbill>;
+
+    /// Bitcoin network whose consensus rules are applied to headers
+    type Network: Get<BitcoinNetwork>;
+
+    /// Registry of staked relayers allowed to flag and clear block errors
+    type StakedRelayers: StakedRelayers<Self::AccountId>;
+
+    /// Status of the BTC Parachain, gating header submission and
+    /// transaction verification
+    type ParachainStatus: ParachainStatus;
+
+    /// Number of blocks below the main chain tip after which a fork
+    /// is considered stale and its headers can be pruned
+    type PruningWindow: Get<u32>;
+
+    /// Number of blocks below the main chain tip whose headers are never
+    /// pruned. Raised to DIFFICULTY_ADJUSTMENT_INTERVAL + MaxReorgDepth + 1
+    /// so that the retarget checks and reorgs find their headers.
+    type RetainedDepth: Get<u32>;
+
+    /// Minimum value (in satoshi) of an output to count towards a
+    /// payment, outputs below it are dust that nodes do not relay
+    type DustValue: Get<i64>;
+
+    /// Maximum number of blocks a reorg may move between the main chain
+    /// and a fork, bounding the weight of a single header submission
+    type MaxReorgDepth: Get<u32>;
+
+    /// Number of confirmations after which a Bitcoin transaction is
+    /// considered stable, until governance sets a different value
+    type StableBitcoinConfirmations: Get<u32>;
+
+    /// Weights of the dispatchables, as measured by the benchmarks
+    type WeightInfo: WeightInfo;
+}
+
+/// Checks that an account is a staked relayer before it can flag or
+/// clear block errors.
+pub trait StakedRelayers<AccountId> {
+    /// Fails with `UnauthorizedRelayer` if the account is not staked
+    ///
+    /// # Arguments
+    ///
+    /// * `relayer` - account id of the relayer
+    fn ensure_staked_relayer(relayer: &AccountId) -> Result<(), Error>;
+}
+
+impl<T: security::Trait> StakedRelayers<T::AccountId> for security::Module<T> {
+    fn ensure_staked_relayer(relayer: &T::AccountId) -> Result<(), Error> {
+        ensure!(
+            Self::check_relayer_registered(relayer.clone()),
+            Error::UnauthorizedRelayer
+        );
+        Ok(())
+    }
+}
+
+/// Exposes the status of the BTC Parachain tracked by the security pallet.
+pub trait ParachainStatus {
+    /// Fails with `Shutdown` if the BTC Parachain has shut down
+    fn ensure_not_shutdown() -> Result<(), Error>;
+
+    /// Fails with `Shutdown` if the BTC Parachain has shut down and with
+    /// `Invalid` if it is in an error state
+    fn ensure_parachain_running() -> Result<(), Error>;
+}
+
+impl<T: security::Trait> ParachainStatus for security::Module<T> {
+    fn ensure_not_shutdown() -> Result<(), Error> {
+        ensure!(
+            !Self::check_parachain_status(StatusCode::Shutdown),
+            Error::Shutdown
+        );
+        Ok(())
+    }
+
+    fn ensure_parachain_running() -> Result<(), Error> {
+        Self::ensure_not_shutdown()?;
+        ensure!(
+            Self::check_parachain_status(StatusCode::Running),
+            Error::Invalid
+        );
+        Ok(())
+    }
+}
+
+/// Without a security pallet the BTC Parachain is always running
+impl ParachainStatus for () {
+    fn ensure_not_shutdown() -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn ensure_parachain_running() -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Notifies dependent pallets of a reorg so that state derived from
+/// now-orphaned transactions can be reverted.
+pub trait OnReorgHandler {
+    /// Called after the main chain was swapped
+    ///
+    /// # Arguments
+    ///
+    /// * `fork_depth` - depth of the fork that became the main chain
+    /// * `orphaned_blocks` - hashes of the blocks removed from the main chain
+    fn on_reorg(fork_depth: u32, orphaned_blocks: Vec<H256Le>);
+}
+
+impl OnReorgHandler for () {
+    fn on_reorg(_fork_depth: u32, _orphaned_blocks: Vec<H256Le>) {}
+}
+
+/// Lets dependent pallets, e.g. issue and redeem, keep the main chain
+/// block headers that their pending requests are verified against.
+pub trait MainChainPruningFilter {
+    /// Returns the lowest main chain height whose header must be kept,
+    /// e.g. the best block height when the oldest pending request was
+    /// opened, or `None` if no header is referenced
+    fn lowest_referenced_height() -> Option<u32>;
+}
+
+impl MainChainPruningFilter for () {
+    fn lowest_referenced_height() -> Option<u32> {
+        None
+    }
+}
+
+/// Weights of the BTC-Relay dispatchables, see `benchmarking.rs`
+pub trait WeightInfo {
+    fn initialize() -> Weight;
+    fn store_block_header_on_main_chain() -> Weight;
+    fn store_block_header_new_fork() -> Weight;
+    /// # Arguments
+    ///
+    /// * `depth` - number of blocks moved between the main chain and the fork
+    fn store_block_header_reorg(depth: u32) -> Weight;
+    /// # Arguments
+    ///
+    /// * `proof_hashes` - number of hashes in the merkle proof
+    fn verify_transaction_inclusion(proof_hashes: u32) -> Weight;
+    /// # Arguments
+    ///
+    /// * `headers` - number of pruned block headers
+    fn prune_stale_headers(headers: u32) -> Weight;
+    /// # Arguments
+    ///
+    /// * `outputs` - number of outputs of the transaction, including the
+    /// strict checks parsing the whole transaction
+    fn validate_transaction(outputs: u32) -> Weight;
+    /// # Arguments
+    ///
+    /// * `outputs` - number of outputs of the transaction
+    /// * `payments` - number of expected payments
+    fn validate_transaction_multi(outputs: u32, payments: u32) -> Weight;
+}
+
+/// Estimates used until the benchmarks are run on reference hardware
+impl WeightInfo for () {
+    fn initialize() -> Weight {
+        10_000
+    }
+
+    fn store_block_header_on_main_chain() -> Weight {
+        10_000
+    }
+
+    fn store_block_header_new_fork() -> Weight {
+        15_000
+    }
+
+    fn store_block_header_reorg(depth: u32) -> Weight {
+        (depth as Weight).saturating_mul(500).saturating_add(20_000)
+    }
+
+    fn verify_transaction_inclusion(proof_hashes: u32) -> Weight {
+        (proof_hashes as Weight).saturating_mul(200).saturating_add(15_000)
+    }
+
+    fn prune_stale_headers(headers: u32) -> Weight {
+        (headers as Weight).saturating_mul(300).saturating_add(20_000)
+    }
+
+    fn validate_transaction(outputs: u32) -> Weight {
+        (outputs as Weight).saturating_mul(200).saturating_add(10_000)
+    }
+
+    fn validate_transaction_multi(outputs: u32, payments: u32) -> Weight {
+        // every expected payment is summed over all outputs
+        (outputs as Weight)
+            .saturating_mul(payments as Weight + 1)
+            .saturating_mul(100)
+            .saturating_add(10_000)
+    }
+}
+
+/// Weight of storing a single block header, i.e. the weight of the most
+/// expensive case: a header triggering a reorg of MaxReorgDepth blocks
+fn store_block_header_weight<T: Trait>() -> Weight {
+    T::WeightInfo::store_block_header_on_main_chain()
+        .max(T::WeightInfo::store_block_header_new_fork())
+        .max(T::WeightInfo::store_block_header_reorg(T::MaxReorgDepth::get()))
 }
 
 /// Difficulty Adjustment Interval
@@ -68,12 +296,71 @@ pub const UNROUNDED_MAX_TARGET: U256 = U256([
     0x00000000ffffffffu64,
 ]);
 
+/// Target of minimum difficulty blocks (nBits 0x1d00ffff)
+/// 0x00000000FFFF0000000000000000000000000000000000000000000000000000
+pub const MIN_DIFFICULTY_TARGET: U256 = U256([
+    0,
+    0,
+    0,
+    0x00000000ffff0000u64,
+]);
+
+/// Expected number of seconds between two blocks
+pub const TARGET_SPACING: u64 = 600;
+
+/// Number of previous blocks used to compute the median time past
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
+/// Maximum number of seconds a block timestamp may be ahead of the
+/// current time (2 hours)
+pub const MAX_FUTURE_BLOCK_TIME: u64 = 7200;
+
 /// Main chain id
 pub const MAIN_CHAIN_ID: u32 = 0;
 
-/// Global security parameter k for stable transactions
+/// Security parameter k for reorgs, and the default number of
+/// confirmations of stable transactions
 pub const STABLE_TRANSACTION_CONFIRMATIONS: u32 = 6;
 
+/// Maximum number of transactions of a block accepted by
+/// verify_small_block_inclusion
+pub const MAX_SMALL_BLOCK_TRANSACTIONS: usize = 2;
+
+/// Size of the inner nodes of a merkle tree (two concatenated hashes).
+/// Transactions of this size are rejected where an inner node could be
+/// passed off as a transaction (CVE-2017-12842).
+pub const MERKLE_INNER_NODE_SIZE: usize = 64;
+
+/// Minimum number of outputs of an accepted payment transaction: the
+/// payment(s) and the OP_RETURN output
+pub const MIN_PAYMENT_TX_OUTPUTS: usize = 2;
+
+/// Size of the smallest transaction output, an 8 byte value and an empty
+/// script, bounding the number of outputs of a raw transaction
+pub const MIN_TRANSACTION_OUTPUT_SIZE: usize = 9;
+
+/// Maximum number of entries returned by a single call to one of the
+/// paginated list functions (headers_at_height, dump_chain)
+pub const MAX_PAGE_SIZE: u32 = 1000;
+
+/// Maximum number of block headers removed by a single call to
+/// prune_stale_headers, bounding its weight
+pub const MAX_PRUNED_HEADERS: u32 = 1000;
+
+/// Number of pruned fork tips kept to detect headers building on them
+pub const MAX_PRUNED_FORKS: usize = 100;
+
+/// Script prefix of the coinbase output committing to the witness merkle
+/// root (BIP141): OP_RETURN, push of 36 bytes, 0xaa21a9ed
+pub const WITNESS_COMMITMENT_HEADER: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+
+/// Reason why a block error was resolved without operator action
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ErrorResolution {
+    /// The flagged block was moved off the main chain by a reorg
+    Reorg,
+}
+
 // This pallet's storage items.
 decl_storage! {
     trait Store for Module<T: Trait> as BTCRelay {
@@ -84,6 +371,10 @@ decl_storage! {
         /// Sorted mapping of BlockChain elements with reference to ChainsIndex
         Chains: linked_map u32 => u32;
 
+        /// Position of each tracked blockchain in Chains, the inverse of
+        /// Chains to find a fork without iterating over all chains
+        ChainsPositions: map u32 => Option<u32>;
+
         /// Store the index for each tracked blockchain
         ChainsIndex: map u32 => BlockChain;
 
@@ -93,25 +384,92 @@ decl_storage! {
         /// Store the height of the best block
         BestBlockHeight: u32;
 
+        /// Store the timestamp of the best block, telling how old the
+        /// relay's view of Bitcoin is
+        BestBlockTimestamp: Moment;
+
         /// Track existing BlockChain entries
         ChainCounter: u32;
+
+        /// Depth of the fork that became the main chain in the last reorg
+        LastReorgDepth: u32;
+
+        /// Store the witness merkle root of SegWit blocks with a
+        /// validated coinbase commitment
+        WitnessRoots: map H256Le => Option<H256Le>;
+
+        /// Tip hash and height of the last MAX_PRUNED_FORKS forks removed
+        /// by pruning, to tell relayers that a parent is too old rather
+        /// than unknown
+        PrunedForks: Vec<(H256Le, u32)>;
+
+        /// Number of confirmations after which a Bitcoin transaction is
+        /// stable as set by governance, overriding StableBitcoinConfirmations
+        StableConfirmations: Option<u32>;
+
+        /// Chains moved off the main chain by force_set_main_chain, and
+        /// forks branching off them, which never replace the main chain
+        /// by work alone
+        ForcedOutChains: map u32 => bool;
+    }
+    add_extra_genesis {
+        /// Raw 80 byte Bitcoin block header the relay starts from.
+        /// The relay is left uninitialized if empty.
+        config(genesis_header): Vec<u8>;
+        /// Bitcoin block height of the genesis header
+        config(genesis_height): u32;
+        build(|config: &GenesisConfig| {
+            if !config.genesis_header.is_empty() {
+                assert!(
+                    config.genesis_header.len() == 80,
+                    "BTC-Relay genesis header must be 80 bytes"
+                );
+                Module::<T>::initialize_relay(
+                    &config.genesis_header, config.genesis_height
+                ).expect("BTC-Relay genesis header can be stored");
+            }
+        });
     }
 }
 
 decl_module! {
     pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+        /// Upper bound of the retarget timespan as a multiple of TARGET_TIMESPAN
+        const RetargetMaxFactor: u32 = T::RetargetMaxFactor::get();
+
+        /// Lower bound of the retarget timespan as a fraction of TARGET_TIMESPAN
+        const RetargetMinFactor: Perbill = T::RetargetMinFactor::get();
+
+        /// Number of blocks below the main chain tip after which forks are pruned
+        const PruningWindow: u32 = T::PruningWindow::get();
+
+        /// Number of blocks below the main chain tip whose headers are never pruned
+        const RetainedDepth: u32 = T::RetainedDepth::get();
+
+        /// Minimum value of an output to count towards a payment
+        const DustValue: i64 = T::DustValue::get();
+
+        /// Maximum number of blocks a reorg may move between the main chain and a fork
+        const MaxReorgDepth: u32 = T::MaxReorgDepth::get();
+
+        /// Default number of confirmations after which a transaction is stable
+        const StableBitcoinConfirmations: u32 = T::StableBitcoinConfirmations::get();
+
         // Initializing events
         fn deposit_event() = default;
 
         // Initialize errors
         // type Error = Error<T>;
 
-        /// One time function to initialize the BTC-Relay with the first block
+        /// One time function to initialize the BTC-Relay with the first block.
+        /// The target of the block is trusted, it is not checked for
+        /// proof-of-work.
         /// # Arguments
         ///
         /// * `block_header_bytes` - 80 byte raw Bitcoin block header.
         /// * `block_height` - Bitcoin block height of the submitted
         /// block header.
+        #[weight = SimpleDispatchInfo::FixedNormal(T::WeightInfo::initialize())]
         fn initialize(
             origin,
             block_header_bytes: Vec<u8>,
@@ -120,39 +478,8 @@ decl_module! {
         {
             let _ = ensure_signed(origin)?;
 
-            // Check if BTC-Relay was already initialized
-            ensure!(!Self::best_block_exists(), Error::AlreadyInitialized);
-
-            // Parse the block header bytes to extract the required info
-            let raw_block_header = header_from_bytes(&block_header_bytes);
-            let basic_block_header = parse_block_header(raw_block_header);
-            let block_header_hash = BlockHeader::block_hash_le(&raw_block_header);
-
-            // construct the BlockChain struct
-            let blockchain = Self::initialize_blockchain(
-                    block_height, block_header_hash);
-            
-            // Create rich block header
-            let block_header = RichBlockHeader {
-                block_hash: block_header_hash,
-                block_header: basic_block_header,
-                block_height: block_height,
-                chain_ref: blockchain.chain_id
-            };
-
-            // Store a new BlockHeader struct in BlockHeaders
-            Self::set_block_header_from_hash(block_header_hash, &block_header);
-
-            // Store a pointer to BlockChain in ChainsIndex
-            Self::set_block_chain_from_id(
-                MAIN_CHAIN_ID, &blockchain);
-
-            // Store the reference to the new BlockChain in Chains
-            Self::set_chain_from_position_and_id(0, MAIN_CHAIN_ID);
-
-            // Set BestBlock and BestBlockHeight to the submitted block
-            Self::set_best_block(block_header_hash);
-            Self::set_best_block_height(block_height);
+            let block_header_hash = Self::initialize_relay(
+                &block_header_bytes, block_height)?;
 
             // Emit a Initialized Event
             Self::deposit_event(Event::Initialized(
@@ -163,118 +490,84 @@ decl_module! {
             Ok(())
         }
 
-        /// Stores a single new block header
+        /// Stores a single new block header. Resubmitting a header that
+        /// is already stored succeeds without changing the relay and emits
+        /// a DuplicateHeaderIgnored event.
         ///
         /// # Arguments
         ///
         /// * `block_header_bytes` - 80 byte raw Bitcoin block header.
+        #[weight = SimpleDispatchInfo::FixedNormal(store_block_header_weight::<T>())]
         fn store_block_header(
             origin, block_header_bytes: Vec<u8>
         ) -> DispatchResult {
             let _ = ensure_signed(origin)?;
-            // Check if BTC _Parachain is in shutdown state.+
-
-            // ensure!(
-            //     !<security::Module<T>>::check_parachain_status(
-            //         StatusCode::Shutdown),
-            //     Error::Shutdown
-            // );
-
-            // Parse the block header bytes to extract the required info
-            let raw_block_header = header_from_bytes(&block_header_bytes);
-            let basic_block_header = Self::verify_block_header(raw_block_header)?;
-            let block_header_hash = BlockHeader::block_hash_le(&raw_block_header);
+            // Check if BTC _Parachain is in shutdown state.
+            T::ParachainStatus::ensure_not_shutdown()?;
+            ensure!(Self::is_fully_initialized(), Error::NotInitialized);
 
-            let prev_header = Self::get_block_header_from_hash(
-                basic_block_header.hash_prev_block
-            )?;
-
-            // get the block chain of the previous header
-            let prev_blockchain = Self::get_block_chain_from_id(
-                prev_header.chain_ref
-            );
-
-            // Update the current block header
-            // check if the prev block is the highest block in the chain
-            // load the previous block header block height
-            let prev_block_height = prev_header.block_height;
-
-            // update the current block header with height and chain ref
-            // Set the height of the block header
-            let current_block_height = prev_block_height + 1;
-
-            // Update the blockchain
-            // check if we create a new blockchain or extend the existing one
-            // print!("Prev max height: {:?} \n", prev_blockchain.max_height);
-            let is_fork = prev_blockchain.max_height != prev_block_height;
-
-            let blockchain = if is_fork {
-                // create new blockchain element
-                Self::create_blockchain(current_block_height, block_header_hash)
-            } else {
-                // extend the current chain
-                Self::extend_blockchain(
-                    current_block_height, &block_header_hash, prev_blockchain)?
-            };
-
-            // Create rich block header
-            let block_header = RichBlockHeader {
-                block_hash: block_header_hash,
-                block_header: basic_block_header,
-                block_height: current_block_height,
-                chain_ref: blockchain.chain_id
-            };
-
-
-            // Store a new BlockHeader struct in BlockHeaders
-            Self::set_block_header_from_hash(block_header_hash, &block_header);
-
-            // Storing the blockchain depends if we extend or create a new chain
-            if is_fork {
-                // create a new chain
-                // Store a pointer to BlockChain in ChainsIndex
-                Self::set_block_chain_from_id(blockchain.chain_id, &blockchain);
-                // Store the reference to the blockchain in Chains
-                Self::insert_sorted(&blockchain);
-            } else {
-                // extended the chain
-                // Update the pointer to BlockChain in ChainsIndex
-                <ChainsIndex>::mutate(blockchain.chain_id, |_b| &blockchain);
-
-                // check if ordering of Chains needs updating
-                Self::check_and_do_reorg(&blockchain)?;
+            Self::do_store_block_header(&block_header_bytes)?;
+            Ok(())
+        }
 
-                if blockchain.chain_id == MAIN_CHAIN_ID {
-                    Self::set_best_block(block_header_hash);
-                    Self::set_best_block_height(current_block_height)
-                }
-            };
-
-            // Determine if this block extends the main chain or a fork
-            let current_best_block = Self::get_best_block();
-
-            // print!("Best block hash: {:?} \n", current_best_block);
-            // print!("Current block hash: {:?} \n", block_header_hash);
-            
-            if current_best_block == block_header_hash {
-                // extends the main chain
-                Self::deposit_event(
-                    Event::StoreMainChainHeader(
-                        current_block_height,
-                        block_header_hash
-                    )
-                );
-            } else {
-            // created a new fork or updated an existing one
-                Self::deposit_event(
-                    Event::StoreForkHeader(
-                        blockchain.chain_id,
-                        current_block_height,
-                        block_header_hash
-                    )
+        /// Stores a batch of block headers in order, failing with the error
+        /// of the first failing header. Headers stored before it remain
+        /// stored and a BatchPartiallyApplied event tells the caller where
+        /// to resume. Headers that are already stored are skipped and not
+        /// counted as stored.
+        ///
+        /// # Arguments
+        ///
+        /// * `block_headers` - 80 byte raw Bitcoin block headers.
+        #[weight = FunctionOf(
+            |args: (&Vec<Vec<u8>>,)| (args.0.len() as Weight)
+                .saturating_mul(store_block_header_weight::<T>()),
+            DispatchClass::Normal,
+            true
+        )]
+        fn store_block_headers(
+            origin, block_headers: Vec<Vec<u8>>
+        ) -> DispatchResult {
+            let _ = ensure_signed(origin)?;
+            // Check if BTC _Parachain is in shutdown state.
+            T::ParachainStatus::ensure_not_shutdown()?;
+            ensure!(Self::is_fully_initialized(), Error::NotInitialized);
+
+            let mut first_height: Option<u32> = None;
+            let mut last_height: u32 = 0;
+            let mut applied: u32 = 0;
+
+            for (index, block_header_bytes) in block_headers.iter().enumerate() {
+                // known headers are skipped by do_store_block_header
+                let is_duplicate = <BlockHeaders>::exists(
+                    BlockHeader::block_hash_le(&header_from_bytes(block_header_bytes))
                 );
-            };
+                let block_height = match Self::do_store_block_header(block_header_bytes) {
+                    Ok(block_height) => block_height,
+                    Err(error) => {
+                        // the headers stored so far are not rolled back
+                        Self::deposit_event(Event::BatchPartiallyApplied(
+                            applied,
+                            index as u32,
+                            error as u8
+                        ));
+                        return Err(error.into());
+                    }
+                };
+                if !is_duplicate {
+                    first_height.get_or_insert(block_height);
+                    last_height = block_height;
+                    applied += 1;
+                }
+            }
 
+            if let Some(first_height) = first_height {
+                Self::deposit_event(Event::StoredHeaderBatch(
+                    first_height,
+                    last_height,
+                    applied
+                ));
+            }
 
             Ok(())
         }
@@ -290,6 +583,13 @@ decl_module! {
         /// * `confirmations` - The number of confirmations needed to accept 
         /// the proof
         /// * `insecure` - determines if checks against recommended global transaction confirmation are to be executed. Recommended: set to `true` 
+        #[weight = FunctionOf(
+            // every hash of the proof takes 32 bytes
+            |args: (&H256Le, &u32, &Vec<u8>, &u32, &bool)|
+                T::WeightInfo::verify_transaction_inclusion((args.2.len() / 32) as u32),
+            DispatchClass::Normal,
+            true
+        )]
         fn verify_transaction_inclusion(
             origin,
             tx_id: H256Le,
@@ -300,47 +600,63 @@ decl_module! {
         -> DispatchResult {
             let _ = ensure_signed(origin)?;
 
-
-            // fail if parachain is not in running state.
-            /*
-            ensure!(<security::Module<T>>::check_parachain_status(StatusCode::Running),
-                Error::<T>::Shutdown);
-            */
-            
-            //let main_chain = Self::get_block_chain_from_id(MAIN_CHAIN_ID);
-            let best_block_height = Self::get_best_block_height();
-
-            let next_best_fork_id = Self::get_chain_id_from_position(1);
-            let next_best_fork_height = Self::get_block_chain_from_id(
-                next_best_fork_id
-                ).max_height;
-
-            // fail if there is an ongoing fork
-            ensure!(best_block_height 
-                    >= next_best_fork_height + STABLE_TRANSACTION_CONFIRMATIONS,
-                    Error::OngoingFork);
-
-            // This call fails if not enough confirmations
-            Self::check_confirmations(
-                best_block_height, 
-                confirmations, 
-                block_height, 
-                insecure)?;
-            
-            let proof_result = Self::verify_merkle_proof(&raw_merkle_proof)?;
-            
-            let rich_header = Self::get_block_header_from_height(
-                &Self::get_block_chain_from_id(MAIN_CHAIN_ID), 
-                block_height
+            Self::verify_transaction_inclusion_unsigned(
+                tx_id,
+                block_height,
+                &raw_merkle_proof,
+                confirmations,
+                insecure
             )?;
 
-            // fail if the transaction hash is invalid
-            ensure!(proof_result.transaction_hash == tx_id,
-                    Error::InvalidTxid);
+            Self::deposit_event(Event::VerifyTransaction(
+                tx_id,
+                block_height,
+                confirmations
+            ));
+
+            Ok(())
+        }
+
+        /// Stores the witness merkle root of a stored block. The coinbase
+        /// transaction is proven to be the first transaction of the block
+        /// and must commit to the root in its witness commitment output.
+        ///
+        /// # Arguments
+        /// * `block_hash` - hash of the stored block header
+        /// * `coinbase_tx` - raw coinbase transaction
+        /// * `coinbase_proof` - raw merkle proof of the coinbase transaction
+        /// * `witness_root` - witness merkle root of the block
+        /// * `witness_reserved_value` - witness reserved value of the
+        /// coinbase input
+        #[weight = FunctionOf(
+            // every hash of the proof takes 32 bytes
+            |args: (&H256Le, &Vec<u8>, &Vec<u8>, &H256Le, &H256Le)|
+                T::WeightInfo::verify_transaction_inclusion((args.2.len() / 32) as u32),
+            DispatchClass::Normal,
+            true
+        )]
+        fn store_witness_root(
+            origin,
+            block_hash: H256Le,
+            coinbase_tx: Vec<u8>,
+            coinbase_proof: Vec<u8>,
+            witness_root: H256Le,
+            witness_reserved_value: H256Le
+        ) -> DispatchResult {
+            let _ = ensure_signed(origin)?;
+            // Check if BTC _Parachain is in shutdown state.
+            T::ParachainStatus::ensure_not_shutdown()?;
+
+            Self::verify_witness_root(
+                block_hash,
+                coinbase_tx,
+                coinbase_proof,
+                witness_root,
+                witness_reserved_value
+            )?;
+            Self::set_witness_root(block_hash, witness_root);
 
-            // fail if the merkle root is invalid
-            ensure!(proof_result.extracted_root == rich_header.block_header.merkle_root,
-                    Error::InvalidMerkleProof);
+            Self::deposit_event(Event::StoreWitnessRoot(block_hash, witness_root));
 
             Ok(())
         }
@@ -355,45 +671,216 @@ decl_module! {
         ///
         /// # Arguments
         /// * `raw_tx` - raw Bitcoin transaction
-        /// * `paymentValue` - value of BTC sent in the 1st / 
-        /// payment UTXO of the transaction
-        /// * `recipientBtcAddress` - 20 byte Bitcoin address of recipient 
-        /// of the BTC in the 1st  / payment UTXO
-        /// * `op_return_id` - 32 byte hash identifier expected in 
+        /// * `paymentValue` - minimum value of BTC sent to the recipient,
+        /// summed over all outputs paying the recipient that are not
+        /// below DustValue
+        /// * `recipientBtcAddress` - address hash (P2PKH, P2SH, P2WPKH)
+        /// or witness program (P2WSH) of the recipient of the BTC
+        /// * `op_return_id` - 32 byte hash identifier expected in
         /// OP_RETURN (replay protection)
+        /// * `strict` - additionally require a transaction version of at
+        /// least 2 and a locktime satisfied at the next main chain block
+        #[weight = FunctionOf(
+            |args: (&Vec<u8>, &i64, &Recipient, &Vec<u8>, &bool)|
+                T::WeightInfo::validate_transaction(
+                    (args.0.len() / MIN_TRANSACTION_OUTPUT_SIZE) as u32
+                ),
+            DispatchClass::Normal,
+            true
+        )]
         fn validate_transaction(
             origin,
             raw_tx: Vec<u8>,
             payment_value: i64,
-            recipient_btc_address: Vec<u8>,
-            op_return_id: Vec<u8>
+            recipient_btc_address: Recipient,
+            op_return_id: Vec<u8>,
+            strict: bool
         ) -> DispatchResult {
             let _ = ensure_signed(origin)?;
 
-            let transaction = Self::parse_transaction(&raw_tx)
-                .map_err(|_e| Error::TxFormat)?;
+            if strict {
+                // version and locktime checks need the full transaction
+                let transaction = Self::parse_transaction(&raw_tx)?;
+                ensure!(transaction.version >= 2, Error::InvalidTxVersion);
+                ensure!(Self::is_transaction_final(&transaction), Error::TxNotFinal);
+            }
+
+            Self::validate_issue_payment(
+                &raw_tx,
+                recipient_btc_address.as_bytes(),
+                payment_value,
+                &op_return_id
+            )?;
+
+            Self::deposit_event(Event::ValidateTransaction(
+                Self::extract_tx_id(&raw_tx)?,
+                payment_value,
+                recipient_btc_address,
+                op_return_id
+            ));
+
+            Ok(())
+        }
+
+        /// Validates a transaction paying several recipients at once, e.g.
+        /// a batch of redeem requests, like validate_transaction does for
+        /// a single recipient. Each `(recipient, value)` pair must be paid
+        /// by the outputs on its own. If a pair is not paid, a
+        /// PaymentRejected event reports its index and the error.
+        ///
+        /// # Arguments
+        /// * `raw_tx` - raw Bitcoin transaction
+        /// * `expected_payments` - pairs of an address hash or witness
+        /// program and the minimum value sent to it
+        /// * `op_return_id` - 32 byte hash identifier expected in
+        /// OP_RETURN (replay protection)
+        #[weight = FunctionOf(
+            |args: (&Vec<u8>, &Vec<(Recipient, i64)>, &Vec<u8>)|
+                T::WeightInfo::validate_transaction_multi(
+                    (args.0.len() / MIN_TRANSACTION_OUTPUT_SIZE) as u32,
+                    args.1.len() as u32
+                ),
+            DispatchClass::Normal,
+            true
+        )]
+        fn validate_transaction_multi(
+            origin,
+            raw_tx: Vec<u8>,
+            expected_payments: Vec<(Recipient, i64)>,
+            op_return_id: Vec<u8>
+        ) -> DispatchResult {
+            let _ = ensure_signed(origin)?;
 
-            // TODO: make 2 a constant
-            ensure!(transaction.outputs.len() >= 2, Error::TxFormat);
+            ensure!(!expected_payments.is_empty(), Error::TxFormat);
 
-            // Check if 1st / payment UTXO transfers sufficient value
-            // FIXME: returns incorrect value (too large: 9865995930474779817)
-            let extr_payment_value = transaction.outputs[0].value;
-            ensure!(extr_payment_value >= payment_value, Error::InsufficientValue);
+            // only the outputs are needed, the inputs are skipped
+            let outputs = Self::parse_transaction_outputs(&raw_tx)?;
+            ensure!(outputs.len() >= MIN_PAYMENT_TX_OUTPUTS, Error::TxFormat);
 
-            // Check if 1st / payment UTXO sends to correct address
-            let extr_recipient_address = extract_address_hash(
-                    &transaction.outputs[0].script
-                ).map_err(|_e| Error::InvalidOutputFormat)?;
-            ensure!(extr_recipient_address == recipient_btc_address, 
-                Error::WrongRecipient);
+            if let Err((index, error)) = Self::check_payments(&outputs, &expected_payments) {
+                Self::deposit_event(Event::PaymentRejected(index, error as u8));
+                return Err(error.into());
+            }
 
-            // Check if 2nd / data UTXO has correct OP_RETURN value
-            let extr_op_return_value = extract_op_return_data(
-                    &transaction.outputs[1].script
-                ).map_err(|_e| Error::InvalidOpreturn)?;
+            // Check if the single data UTXO has correct OP_RETURN value
+            let extr_op_return_value = Self::get_op_return_from_outputs(&outputs)?;
             ensure!(extr_op_return_value == op_return_id, Error::InvalidOpreturn);
 
+            Self::deposit_event(Event::ValidateTransactionMulti(
+                Self::extract_tx_id(&raw_tx)?,
+                expected_payments,
+                op_return_id
+            ));
+
+            Ok(())
+        }
+
+        /// Removes the block headers of stale forks, i.e. forks whose tip
+        /// is more than PruningWindow blocks below the main chain tip.
+        /// At most MAX_PRUNED_HEADERS headers are removed per call, a fork
+        /// exceeding the remaining budget is shortened from its tip.
+        /// Headers building on the tip of one of the last
+        /// MAX_PRUNED_FORKS pruned forks are rejected with
+        /// BlockBelowPruningWindow. Only these tips are remembered,
+        /// headers building on any other pruned header, e.g. below a
+        /// pruned tip or of an older pruned fork, fail with PrevBlock.
+        /// The remaining budget removes the oldest main chain headers
+        /// more than RetainedDepth blocks below the tip, unless a
+        /// dependent pallet still references them (see
+        /// MainChainPruningFilter) or a fork builds on them.
+        /// Can only be called by root.
+        ///
+        /// # Arguments
+        /// * `up_to_height` - only forks with a tip at or below this
+        /// height and main chain headers at or below this height are
+        /// pruned
+        #[weight = SimpleDispatchInfo::FixedOperational(
+            T::WeightInfo::prune_stale_headers(MAX_PRUNED_HEADERS)
+        )]
+        fn prune_stale_headers(origin, up_to_height: u32) -> DispatchResult {
+            ensure_root(origin)?;
+
+            let mut pruned = Self::prune_stale_chains(up_to_height)?;
+            pruned += Self::prune_main_chain(up_to_height, MAX_PRUNED_HEADERS - pruned)?;
+
+            Self::deposit_event(Event::PrunedHeaders(pruned, up_to_height));
+
+            Ok(())
+        }
+
+        /// Promotes a fork to the main chain regardless of its work, e.g.
+        /// to recover from following a chain flagged as invalid. The
+        /// replaced main chain no longer causes reorgs when extended.
+        /// Can only be called by root.
+        ///
+        /// # Arguments
+        /// * `chain_id` - id of a fork branching off the main chain
+        #[weight = SimpleDispatchInfo::FixedOperational(store_block_header_weight::<T>())]
+        fn force_set_main_chain(origin, chain_id: u32) -> DispatchResult {
+            ensure_root(origin)?;
+
+            let old_best_block = Self::get_best_block();
+            Self::force_main_chain(chain_id)?;
+
+            Self::deposit_event(Event::ForcedReorg(
+                chain_id,
+                Self::get_best_block(),
+                old_best_block,
+            ));
+
+            Ok(())
+        }
+
+        /// Sets the number of confirmations after which Bitcoin
+        /// transactions are considered stable, e.g. to require deeper
+        /// inclusion while the threat level is raised. Can only be called
+        /// by root.
+        ///
+        /// # Arguments
+        /// * `value` - the new number of stable confirmations
+        #[weight = SimpleDispatchInfo::FixedOperational(10_000)]
+        fn set_stable_confirmations(origin, value: u32) -> DispatchResult {
+            ensure_root(origin)?;
+
+            let old_value = Self::get_stable_transaction_confirmations();
+            <StableConfirmations>::put(value);
+
+            Self::deposit_event(Event::StableConfirmationsSet(value, old_value));
+
+            Ok(())
+        }
+
+        /// Sets the cached total work of a chain, e.g. to correct the
+        /// work computed by a migration or a checkpoint seed. The work is
+        /// compared with the other chains right away: a fork whose work
+        /// now exceeds the reorg threshold of the main chain replaces it.
+        /// Can only be called by root.
+        ///
+        /// # Arguments
+        /// * `chain_id` - id of the main chain or a fork
+        /// * `work` - the new total work of the chain
+        #[weight = SimpleDispatchInfo::FixedOperational(store_block_header_weight::<T>())]
+        fn set_chain_work(origin, chain_id: u32, work: U256) -> DispatchResult {
+            ensure_root(origin)?;
+            ensure!(Self::block_chain_exists(chain_id), Error::ForkIdNotFound);
+
+            let mut blockchain = Self::get_block_chain_from_id(chain_id);
+            let old_work = blockchain.total_work;
+            blockchain.total_work = work;
+            Self::set_block_chain_from_id(chain_id, &blockchain);
+
+            Self::deposit_event(Event::ChainWorkSet(chain_id, work, old_work));
+
+            if chain_id == MAIN_CHAIN_ID {
+                // lowering the main chain's work may let any fork overtake
+                // it, the heaviest fork is the one to compare
+                if let Some(fork) = Self::get_heaviest_fork() {
+                    Self::check_and_do_reorg(&fork)?;
+                }
+            } else {
+                Self::check_and_do_reorg(&blockchain)?;
+            }
+
             Ok(())
         }
     }
@@ -411,28 +898,29 @@ impl<T: Trait> Module<T> {
     }
     /// Get the position of the fork in Chains
     fn get_chain_position_from_chain_id(chain_id: u32) -> Result<u32, Error> {
-        for (k,v) in <Chains>::enumerate() {
-            if v == chain_id {
-                return Ok(k);
-            }
-        }
-        Err(Error::ForkIdNotFound)
-    }
-    //    match <Chains>::enumerate()
-    //        .position(|(_k, v)| v == chain_id)
-    //    {
-    //        Some(pos) => return Ok(pos as u32),
-    //        None => return Err(Error::ForkIdNotFound),
-    //    };
-    //}
+        <ChainsPositions>::get(chain_id).ok_or(Error::ForkIdNotFound)
+    }
     /// Get a blockchain from the id
     // TODO: the return of this element can an empty element when it was deleted
     // Function should be changed to return a Result or Option
     fn get_block_chain_from_id(chain_id: u32) -> BlockChain {
         <ChainsIndex>::get(chain_id)
     }
+    /// Check if a block header was the tip of one of the last
+    /// MAX_PRUNED_FORKS pruned forks
+    fn is_block_pruned(block_hash: H256Le) -> bool {
+        <PrunedForks>::get().iter().any(|(tip, _height)| *tip == block_hash)
+    }
+    /// Check if a blockchain element with the given id exists
+    fn block_chain_exists(chain_id: u32) -> bool {
+        <ChainsIndex>::exists(chain_id)
+    }
+    /// Check if a chain was forced out of the main chain by root
+    fn is_forced_out(chain_id: u32) -> bool {
+        <ForcedOutChains>::get(chain_id)
+    }
     /// Get the current best block hash
-    fn get_best_block() -> H256Le {
+    pub fn get_best_block() -> H256Le {
         <BestBlock>::get()
     }
     /// Check if a best block hash is set
@@ -440,13 +928,30 @@ impl<T: Trait> Module<T> {
         <BestBlock>::exists()
     }
     /// get the best block height 
-    fn get_best_block_height() -> u32 {
+    pub fn get_best_block_height() -> u32 {
         <BestBlockHeight>::get()
     }
+    /// Get the timestamp of the best block
+    pub fn get_best_block_timestamp() -> Moment {
+        <BestBlockTimestamp>::get()
+    }
+    /// Get the depth of the last reorg
+    fn get_last_reorg_depth() -> u32 {
+        <LastReorgDepth>::get()
+    }
+    /// Get the number of tracked blockchains, including the main chain
+    fn get_chain_count() -> u32 {
+        <Chains>::enumerate().count() as u32
+    }
     /// Get the current chain counter
     fn get_chain_counter() -> u32 {
         <ChainCounter>::get()
     }
+    /// Get the witness merkle root of a SegWit block, if it was stored
+    /// together with a validated coinbase commitment
+    pub fn get_witness_root(block_hash: H256Le) -> Option<H256Le> {
+        <WitnessRoots>::get(block_hash)
+    }
     /// Get a block hash from a blockchain
     /// # Arguments
     ///
@@ -485,22 +990,48 @@ impl<T: Trait> Module<T> {
     /// Storage setter functions
     /// Set a new chain with position and id
     fn set_chain_from_position_and_id(position: u32, id: u32) {
+        if <Chains>::exists(position) {
+            <ChainsPositions>::remove(<Chains>::get(position));
+        }
+        <Chains>::insert(position, id);
+        <ChainsPositions>::insert(id, position);
+    }
+    /// Set the chain id at a position, leaving the entry at its previous
+    /// position to the caller
+    fn move_chain_to_position(position: u32, id: u32) {
         <Chains>::insert(position, id);
+        <ChainsPositions>::insert(id, position);
     }
     /// Swap chain elements
     fn swap_chain(pos_1: u32, pos_2: u32) {
-        <Chains>::swap(pos_1, pos_2)
+        <Chains>::swap(pos_1, pos_2);
+        <ChainsPositions>::insert(<Chains>::get(pos_1), pos_1);
+        <ChainsPositions>::insert(<Chains>::get(pos_2), pos_2);
     }
-    /// Remove a chain id from chains
+    /// Remove the chain id at a position from Chains
+    fn remove_chain_from_position(position: u32) {
+        <ChainsPositions>::remove(<Chains>::get(position));
+        <Chains>::remove(position);
+    }
+    /// Get the number of positions in Chains. The positions are
+    /// contiguous and the head of the mapping is the last position.
+    fn get_chains_length() -> u32 {
+        <Chains>::head().map_or(0, |head| head + 1)
+    }
+    /// Remove a chain id from chains, moving the chains behind it up by
+    /// one position to keep Chains sorted
     fn remove_blockchain_from_chain(position: u32) -> Result<(), Error> {
-        // swap the element with the last element in the mapping
+        // the last element in the mapping
         let head_index = match <Chains>::head() {
             Some(head) => head,
             None => return Err(Error::ForkIdNotFound),
         };
-        <Chains>::swap(position, head_index);
-        // remove the header (now the value at the initial position)
-        <Chains>::remove(head_index);
+        // move the element to the last position
+        for curr_position in position..head_index {
+            Self::swap_chain(curr_position, curr_position + 1);
+        }
+        // remove the element (now the value at the last position)
+        Self::remove_chain_from_position(head_index);
         Ok(())
     }
     /// Set a new blockchain in ChainsIndex
@@ -526,14 +1057,27 @@ impl<T: Trait> Module<T> {
         });
     }
 
+    /// Set the witness merkle root of a block
+    fn set_witness_root(block_hash: H256Le, witness_root: H256Le) {
+        <WitnessRoots>::insert(block_hash, witness_root);
+    }
+
     /// Set a new best block
     fn set_best_block(hash: H256Le) {
         <BestBlock>::put(hash);
     }
+    /// Set the depth of the last reorg
+    fn set_last_reorg_depth(depth: u32) {
+        <LastReorgDepth>::put(depth);
+    }
     /// Set a new best block height
     fn set_best_block_height(height: u32) {
         <BestBlockHeight>::put(height);
     }
+    /// Set the timestamp of the best block
+    fn set_best_block_timestamp(timestamp: Moment) {
+        <BestBlockTimestamp>::put(timestamp);
+    }
     /// Set a new chain counter
     fn increment_chain_counter() -> u32 {
         let new_counter = Self::get_chain_counter() + 1;
@@ -544,28 +1088,46 @@ impl<T: Trait> Module<T> {
     
     /// Initialize the new main blockchain with a single block
     fn initialize_blockchain(
-        block_height: u32, block_hash: H256Le
+        block_height: u32, block_hash: H256Le, target: U256
     ) -> BlockChain {
         let chain_id = MAIN_CHAIN_ID;
 
+        // the work of the relay starts with its first block
+        let total_work = Self::block_work(target);
+
         // generate an empty blockchain
-        Self::generate_blockchain(chain_id, block_height, block_hash)
+        Self::generate_blockchain(chain_id, block_height, block_hash, total_work)
     }
     /// Create a new blockchain element with a new chain id
+    ///
+    /// # Arguments
+    ///
+    /// * `block_height` - height of the first block of the fork
+    /// * `block_hash` - hash of the first block of the fork
+    /// * `target` - target of the first block of the fork
+    /// * `prev_blockchain` - the chain the fork branches off from
     fn create_blockchain(
-        block_height: u32, block_hash: H256Le
+        block_height: u32,
+        block_hash: H256Le,
+        target: U256,
+        prev_blockchain: &BlockChain,
     ) -> BlockChain {
         // get a new chain id
         let chain_id: u32 = Self::increment_chain_counter();
 
+        // the fork shares the work of the chain below the fork point
+        let total_work = Self::chain_work_at(prev_blockchain, block_height - 1)
+            .saturating_add(Self::block_work(target));
+
         // generate an empty blockchain
-        Self::generate_blockchain(chain_id, block_height, block_hash)
+        Self::generate_blockchain(chain_id, block_height, block_hash, total_work)
     }
     /// Generate the raw blockchain from a chain Id and with a single block 
     fn generate_blockchain(
         chain_id: u32,
         block_height: u32,
         block_hash: H256Le,
+        total_work: U256,
     ) -> BlockChain {
         // initialize an empty chain
         let mut chain = BTreeMap::new();
@@ -579,6 +1141,7 @@ impl<T: Trait> Module<T> {
             max_height: block_height,
             no_data: BTreeSet::new(),
             invalid: BTreeSet::new(),
+            total_work: total_work,
         };
         blockchain
     }
@@ -586,35 +1149,279 @@ impl<T: Trait> Module<T> {
     fn extend_blockchain(
         block_height: u32,
         block_hash: &H256Le,
+        target: U256,
         prev_blockchain: BlockChain,
     ) -> Result<BlockChain, Error> {
         let mut blockchain = prev_blockchain;
 
+        // only the block directly on top of the chain tip can be added
+        ensure!(
+            blockchain.max_height.checked_add(1) == Some(block_height),
+            Error::BlockHeightNotContiguous
+        );
+
         if blockchain.chain.insert(block_height, *block_hash).is_some() {
             return Err(Error::DuplicateBlock.into());
         }
 
         blockchain.max_height = block_height;
+        blockchain.total_work = blockchain
+            .total_work
+            .saturating_add(Self::block_work(target));
 
         Ok(blockchain)
     }
 
     // Get require conformations for stable transactions
     fn get_stable_transaction_confirmations() -> u32 {
-        STABLE_TRANSACTION_CONFIRMATIONS
+        <StableConfirmations>::get().unwrap_or_else(T::StableBitcoinConfirmations::get)
     }
-    // *********************************
-    // END: Storage getter functions
-    // *********************************
-
-
-    // Wrapper functions around bitcoin lib for testing purposes
-    
+    // Get the upper bound factor of the retarget timespan
+    fn get_retarget_max_factor() -> u32 {
+        T::RetargetMaxFactor::get()
+    }
+    // Get the number of blocks after which a fork is stale
+    fn get_pruning_window() -> u32 {
+        T::PruningWindow::get()
+    }
+    // Get the number of blocks below the main chain tip that are never
+    // pruned, covering the headers needed by retarget checks and reorgs
+    fn get_retained_depth() -> u32 {
+        T::RetainedDepth::get()
+            .max(DIFFICULTY_ADJUSTMENT_INTERVAL + Self::get_max_reorg_depth() + 1)
+    }
+    // Get the maximum depth of a reorg
+    fn get_max_reorg_depth() -> u32 {
+        T::MaxReorgDepth::get()
+    }
+    // Get the minimum value of an output counting towards a payment
+    fn get_dust_value() -> i64 {
+        T::DustValue::get()
+    }
+    // Get the lower bound factor of the retarget timespan
+    fn get_retarget_min_factor() -> Perbill {
+        T::RetargetMinFactor::get()
+    }
+    // Get the Bitcoin network whose consensus rules are applied
+    fn get_network() -> BitcoinNetwork {
+        T::Network::get()
+    }
+    // Get the current on-chain time in seconds since the unix epoch
+    fn get_current_time() -> u64 {
+        <timestamp::Module<T>>::get().saturated_into::<u64>() / 1000
+    }
+    // *********************************
+    // END: Storage getter functions
+    // *********************************
+
+
+    /// Stores the first block header of the relay as the main chain tip.
+    /// Used by `initialize` and the genesis config.
+    ///
+    /// The relay usually starts mid-history, so there is no previous block
+    /// to derive the expected target from: the header is not checked for
+    /// proof-of-work and its target is trusted. The next header must use
+    /// this target unless it starts a new retarget interval.
+    ///
+    /// # Arguments
+    ///
+    /// * `block_header_bytes` - 80 byte raw Bitcoin block header.
+    /// * `block_height` - Bitcoin block height of the submitted
+    /// block header.
+    ///
+    /// # Returns
+    /// * the hash of the stored block header
+    fn initialize_relay(block_header_bytes: &[u8], block_height: u32) -> Result<H256Le, Error> {
+        // Check if BTC-Relay was already initialized
+        ensure!(!Self::best_block_exists(), Error::AlreadyInitialized);
+
+        // Parse the block header bytes to extract the required info
+        let raw_block_header = header_from_bytes(&block_header_bytes);
+        let basic_block_header = parse_block_header(raw_block_header);
+        let block_header_hash = BlockHeader::block_hash_le(&raw_block_header);
+
+        // construct the BlockChain struct
+        let blockchain = Self::initialize_blockchain(
+                block_height, block_header_hash, basic_block_header.target);
+        
+        // Create rich block header
+        let block_header = RichBlockHeader {
+            block_hash: block_header_hash,
+            block_header: basic_block_header,
+            block_height: block_height,
+            chain_ref: blockchain.chain_id
+        };
+
+        // Store a new BlockHeader struct in BlockHeaders
+        Self::set_block_header_from_hash(block_header_hash, &block_header);
+
+        // Store a pointer to BlockChain in ChainsIndex
+        Self::set_block_chain_from_id(
+            MAIN_CHAIN_ID, &blockchain);
+
+        // Store the reference to the new BlockChain in Chains
+        Self::set_chain_from_position_and_id(0, MAIN_CHAIN_ID);
+
+        // Set BestBlock and BestBlockHeight to the submitted block
+        Self::set_best_block(block_header_hash);
+        Self::set_best_block_height(block_height);
+        Self::set_best_block_timestamp(basic_block_header.timestamp);
+
+        Ok(block_header_hash)
+    }
+
+    /// Verifies and stores a single block header, extending the main
+    /// chain or a fork. Returns the height of the stored header, or of
+    /// the already stored header if it is a duplicate.
+    ///
+    /// # Arguments
+    ///
+    /// * `block_header_bytes` - 80 byte raw Bitcoin block header.
+    fn do_store_block_header(block_header_bytes: &[u8]) -> Result<u32, Error> {
+        // Parse the block header bytes to extract the required info
+        let raw_block_header = header_from_bytes(&block_header_bytes);
+        let block_header_hash = BlockHeader::block_hash_le(&raw_block_header);
+
+        // relayers resubmit overlapping ranges: headers that are already
+        // stored are skipped without touching the chains
+        if <BlockHeaders>::exists(block_header_hash) {
+            let block_height = Self::get_block_header_from_hash(block_header_hash)?.block_height;
+            Self::deposit_event(Event::DuplicateHeaderIgnored(block_height, block_header_hash));
+            return Ok(block_height);
+        }
+
+        let basic_block_header = Self::verify_block_header(raw_block_header)?;
+
+        let prev_header = Self::get_block_header_from_hash(
+            basic_block_header.hash_prev_block
+        )?;
+
+        // fail if the chain of the previous header was removed
+        ensure!(
+            Self::block_chain_exists(prev_header.chain_ref),
+            Error::ForkIdNotFound
+        );
+
+        // get the block chain of the previous header
+        let prev_blockchain = Self::get_block_chain_from_id(
+            prev_header.chain_ref
+        );
+
+        // Update the current block header
+        // check if the prev block is the highest block in the chain
+        // load the previous block header block height
+        let prev_block_height = prev_header.block_height;
+
+        // update the current block header with height and chain ref
+        // Set the height of the block header
+        let current_block_height = prev_block_height + 1;
+
+        // Update the blockchain
+        // check if we create a new blockchain or extend the existing one
+        // print!("Prev max height: {:?} \n", prev_blockchain.max_height);
+        let is_fork = prev_blockchain.max_height != prev_block_height;
+
+        let blockchain = if is_fork {
+            // create new blockchain element
+            Self::create_blockchain(
+                current_block_height,
+                block_header_hash,
+                basic_block_header.target,
+                &prev_blockchain,
+            )
+        } else {
+            // extend the current chain
+            Self::extend_blockchain(
+                current_block_height,
+                &block_header_hash,
+                basic_block_header.target,
+                prev_blockchain,
+            )?
+        };
+
+        // Create rich block header
+        let block_header = RichBlockHeader {
+            block_hash: block_header_hash,
+            block_header: basic_block_header,
+            block_height: current_block_height,
+            chain_ref: blockchain.chain_id
+        };
+
+
+        // Store a new BlockHeader struct in BlockHeaders
+        Self::set_block_header_from_hash(block_header_hash, &block_header);
+
+        // Storing the blockchain depends if we extend or create a new chain
+        if is_fork {
+            // create a new chain
+            // Store a pointer to BlockChain in ChainsIndex
+            Self::set_block_chain_from_id(blockchain.chain_id, &blockchain);
+            // forks of a forced out chain are forced out as well
+            if Self::is_forced_out(prev_blockchain.chain_id) {
+                <ForcedOutChains>::insert(blockchain.chain_id, true);
+            }
+            // Store the reference to the blockchain in Chains
+            Self::insert_sorted(&blockchain);
+        } else {
+            // extended the chain
+            // Update the pointer to BlockChain in ChainsIndex
+            <ChainsIndex>::mutate(blockchain.chain_id, |_b| &blockchain);
+
+            // check if ordering of Chains needs updating
+            Self::check_and_do_reorg(&blockchain)?;
+
+            if blockchain.chain_id == MAIN_CHAIN_ID {
+                Self::set_best_block(block_header_hash);
+                Self::set_best_block_height(current_block_height);
+                Self::set_best_block_timestamp(basic_block_header.timestamp);
+            }
+        };
+
+        // Determine if this block extends the main chain or a fork
+        let current_best_block = Self::get_best_block();
+
+        // print!("Best block hash: {:?} \n", current_best_block);
+        // print!("Current block hash: {:?} \n", block_header_hash);
+    
+        if current_best_block == block_header_hash {
+            // extends the main chain
+            Self::deposit_event(
+                Event::StoreMainChainHeader(
+                    current_block_height,
+                    block_header_hash
+                )
+            );
+        } else {
+        // created a new fork or updated an existing one
+            Self::deposit_event(
+                Event::StoreForkHeader(
+                    blockchain.chain_id,
+                    current_block_height,
+                    block_header_hash
+                )
+            );
+        };
+
+        Ok(current_block_height)
+    }
+
+    // Wrapper functions around bitcoin lib for testing purposes
+    
     fn parse_transaction(raw_tx: &[u8]) -> Result<Transaction, Error> {
         parse_transaction(&raw_tx)
                 .map_err(|_e| Error::TxFormat)
     }
 
+    fn parse_transaction_outputs(raw_tx: &[u8]) -> Result<Vec<TransactionOutput>, Error> {
+        parse_transaction_outputs(&raw_tx)
+                .map_err(|_e| Error::TxFormat)
+    }
+
+    fn extract_tx_id(raw_tx: &[u8]) -> Result<H256Le, Error> {
+        extract_tx_id(&raw_tx)
+                .map_err(|_e| Error::TxFormat)
+    }
+
     fn verify_merkle_proof(raw_merkle_proof: &[u8]) -> Result<ProofResult, Error> {
 
         let merkle_proof = MerkleProof::parse(&raw_merkle_proof)
@@ -624,6 +1431,11 @@ impl<T: Trait> Module<T> {
             .verify_proof()
             .map_err(|_e| Error::InvalidMerkleProof)
     }
+    /// Returns the hash of the block header a raw merkle proof starts with
+    fn merkle_proof_block_hash(raw_merkle_proof: &[u8]) -> Result<H256Le, Error> {
+        ensure!(raw_merkle_proof.len() >= 80, Error::InvalidMerkleProof);
+        Ok(BlockHeader::block_hash_le(&raw_merkle_proof[..80]))
+    }
     /// Parses and verifies a raw Bitcoin block header.
     /// # Arguments
     /// * block_header` - 80-byte block header
@@ -636,6 +1448,9 @@ impl<T: Trait> Module<T> {
     fn verify_block_header(raw_block_header: RawBlockHeader) -> Result<BlockHeader, Error> {
         let basic_block_header = parse_block_header(raw_block_header);
 
+        // Check that the target is encoded the way Bitcoin Core encodes it
+        ensure!(basic_block_header.has_canonical_bits(), Error::DiffTargetHeader);
+
         let block_header_hash = BlockHeader::block_hash_le(&raw_block_header);
 
         // Check that the block header is not yet stored in BTC-Relay
@@ -647,26 +1462,167 @@ impl<T: Trait> Module<T> {
         // Check that the referenced previous block header exists in BTC-Relay
         let prev_block_header = Self::get_block_header_from_hash(
             basic_block_header.hash_prev_block)
-            .map_err(|_| Error::PrevBlock)?;
-        // Check that the PoW hash satisfies the target set in the block header
+            .map_err(|_| match Self::is_block_pruned(basic_block_header.hash_prev_block) {
+                true => Error::BlockBelowPruningWindow,
+                false => Error::PrevBlock,
+            })?;
+        // Regtest headers do not need to meet any difficulty
+        if !Self::pow_checks_disabled() {
+            // Check that the PoW hash satisfies the target set in the block header
+            ensure!(
+                block_header_hash.as_u256() < basic_block_header.target,
+                Error::LowDiff
+            );
+
+            // Check that the diff. target is indeed correctly set in the block header, i.e., check for re-target.
+            // Testnet allows a minimum difficulty block if no block was found
+            // for 20 minutes
+            let block_height = prev_block_header.block_height + 1;
+            let allow_min_difficulty = Self::get_network() == BitcoinNetwork::Testnet
+                && block_height % DIFFICULTY_ADJUSTMENT_INTERVAL != 0
+                && basic_block_header.timestamp
+                    > prev_block_header.block_header.timestamp + 2 * TARGET_SPACING;
+
+            // The relay usually starts mid-history: if the retarget interval
+            // began before the first stored block, its timestamp is unknown.
+            // The first block is trusted, so is the interval it is part of,
+            // and the new target is only checked to be reachable by a retarget.
+            let retarget_interval_unknown = block_height % DIFFICULTY_ADJUSTMENT_INTERVAL == 0
+                && !Self::has_ancestors(prev_block_header.block_height, DIFFICULTY_ADJUSTMENT_INTERVAL);
+
+            if retarget_interval_unknown {
+                ensure!(
+                    Self::is_permitted_retarget(
+                        prev_block_header.block_header.target,
+                        basic_block_header.target
+                    )?,
+                    Error::DiffTargetHeader
+                );
+            } else {
+                let expected_target = match allow_min_difficulty {
+                    true => MIN_DIFFICULTY_TARGET,
+                    false => Self::expected_target(&prev_block_header)?
+                };
+
+                // Compare the compact encodings like Bitcoin Core does
+                ensure!(
+                    basic_block_header.bits == nbits_from_target(expected_target),
+                    Error::DiffTargetHeader
+                );
+            }
+        }
+
+        // Check that the timestamp is greater than the median of the previous
+        // blocks. The median is skipped until MEDIAN_TIME_SPAN blocks are
+        // stored, as the blocks before the trusted first block are unknown
+        // and a partial median may exceed the actual one.
+        if Self::has_ancestors(prev_block_header.block_height, MEDIAN_TIME_SPAN as u32) {
+            ensure!(
+                basic_block_header.timestamp > Self::median_time_past(prev_block_header.block_hash),
+                Error::TimestampTooOld
+            );
+        }
+
+        // Check that the timestamp is not too far in the future
         ensure!(
-            block_header_hash.as_u256() < basic_block_header.target,
-            Error::LowDiff
+            basic_block_header.timestamp
+                <= Self::get_current_time().saturating_add(MAX_FUTURE_BLOCK_TIME),
+            Error::TimestampTooFarAhead
         );
 
-        // Check that the diff. target is indeed correctly set in the block header, i.e., check for re-target.
+        Ok(basic_block_header)
+    }
+
+    /// Checks if BTC-Relay stores the block at the given height and the
+    /// `count - 1` blocks before it, i.e. if none of them precedes the
+    /// block the relay was initialized with
+    /// # Arguments
+    ///  * `block_height`: height of the most recent block
+    ///  * `count`: number of blocks, including the most recent one
+    fn has_ancestors(block_height: u32, count: u32) -> bool {
+        let start_height = Self::get_block_chain_from_id(MAIN_CHAIN_ID).start_height;
+        block_height >= start_height.saturating_add(count.saturating_sub(1))
+    }
+
+    /// Checks if the proof-of-work and re-target checks are disabled.
+    /// This is only the case on regtest and only if the pallet is built
+    /// with the `regtest` feature, so that a production runtime cannot
+    /// disable the checks by configuration alone.
+    fn pow_checks_disabled() -> bool {
+        cfg!(any(test, feature = "regtest"))
+            && Self::get_network() == BitcoinNetwork::Regtest
+    }
+
+    /// Returns the target the block following the given block must use,
+    /// i.e. the computed re-target on a difficulty adjustment boundary
+    /// and the target of the given block otherwise
+    /// # Arguments
+    ///  * `prev_block_header`: the block the next block builds on
+    fn expected_target(prev_block_header: &RichBlockHeader) -> Result<U256, Error> {
         let block_height = prev_block_header.block_height + 1;
 
-        let expected_target = match block_height >= 2016 && block_height % DIFFICULTY_ADJUSTMENT_INTERVAL == 0 {
+        match block_height >= 2016 && block_height % DIFFICULTY_ADJUSTMENT_INTERVAL == 0 {
             true => Self::compute_new_target(
-                &prev_block_header, 
-                block_height)?, 
-            false => prev_block_header.block_header.target
-        };
+                prev_block_header,
+                block_height),
+            false => match Self::get_network() {
+                BitcoinNetwork::Testnet => Ok(Self::last_non_min_difficulty_target(prev_block_header)),
+                _ => Ok(prev_block_header.block_header.target)
+            }
+        }
+    }
 
-        ensure!(basic_block_header.target ==  expected_target, Error::DiffTargetHeader);
+    /// Returns the target of the most recent block that is not a testnet
+    /// minimum difficulty block, walking back until the last retarget
+    /// # Arguments
+    ///  * `block_header`: the block to start walking back from
+    fn last_non_min_difficulty_target(block_header: &RichBlockHeader) -> U256 {
+        let mut current = *block_header;
 
-        Ok(basic_block_header)
+        while current.block_header.target == MIN_DIFFICULTY_TARGET
+            && current.block_height % DIFFICULTY_ADJUSTMENT_INTERVAL != 0
+        {
+            match Self::get_block_header_from_hash(current.block_header.hash_prev_block) {
+                Ok(prev) => current = prev,
+                // stop at the first block stored in BTC-Relay
+                Err(_) => break,
+            }
+        }
+
+        current.block_header.target
+    }
+
+    /// Checks if a transaction could be included in the next main chain
+    /// block, given the median time past of the current main chain tip
+    /// # Arguments
+    ///  * `transaction`: the parsed transaction
+    fn is_transaction_final(transaction: &Transaction) -> bool {
+        let next_height = Self::get_best_block_height().saturating_add(1);
+        let block_time = Self::median_time_past(Self::get_best_block());
+        transaction.is_final(next_height, block_time)
+    }
+
+    /// Computes the median timestamp of the given block and up to
+    /// MEDIAN_TIME_SPAN - 1 of its ancestors stored in BTC-Relay
+    /// # Arguments
+    ///  * `block_hash`: hash of the most recent block
+    fn median_time_past(block_hash: H256Le) -> u64 {
+        let mut timestamps = Vec::with_capacity(MEDIAN_TIME_SPAN);
+        let mut current_hash = block_hash;
+
+        while timestamps.len() < MEDIAN_TIME_SPAN {
+            match Self::get_block_header_from_hash(current_hash) {
+                Ok(rich_header) => {
+                    timestamps.push(rich_header.block_header.timestamp);
+                    current_hash = rich_header.block_header.hash_prev_block;
+                },
+                // stop at the first block stored in BTC-Relay
+                Err(_) => break,
+            }
+        }
+
+        timestamps.sort();
+        timestamps.get(timestamps.len() / 2).cloned().unwrap_or(0)
     }
 
 
@@ -679,20 +1635,60 @@ impl<T: Trait> Module<T> {
         // get time of last retarget
         let last_retarget_time = Self::get_last_retarget_time(prev_block_header.chain_ref, block_height)?;
         // Compute new target
-        let actual_timespan = match ((prev_block_header.block_header.timestamp - last_retarget_time) as u32) < (TARGET_TIMESPAN / TARGET_TIMESPAN_DIVISOR) {
-            true => TARGET_TIMESPAN / TARGET_TIMESPAN_DIVISOR,
-            false => TARGET_TIMESPAN * TARGET_TIMESPAN_DIVISOR,
-        };
+        let actual_timespan = Self::clamp_timespan(
+            prev_block_header.block_header.timestamp
+                .saturating_sub(last_retarget_time)
+                .saturated_into::<u32>()
+        );
 
-        let new_target = U256::from(actual_timespan) * prev_block_header.block_header.target / U256::from(TARGET_TIMESPAN);
+        Self::retarget(prev_block_header.block_header.target, actual_timespan)
+    }
+
+    /// Checks if a retarget can change the previous target to the new
+    /// target, i.e. if the new target lies between the targets of the
+    /// shortest and the longest clamped timespan (Bitcoin's
+    /// PermittedDifficultyTransition)
+    /// # Arguments
+    ///  * `prev_target`: target of the last block of the retarget interval
+    ///  * `new_target`: target of the first block of the next interval
+    fn is_permitted_retarget(prev_target: U256, new_target: U256) -> Result<bool, Error> {
+        let min_target = Self::retarget(prev_target, Self::clamp_timespan(0))?;
+        let max_target = Self::retarget(prev_target, Self::clamp_timespan(u32::max_value()))?;
+        Ok(new_target >= min_target && new_target <= max_target)
+    }
+
+    /// Scales the target by the ratio of the timespan to TARGET_TIMESPAN,
+    /// caps it at the maximum target and rounds it to the compact encoding
+    /// # Arguments
+    ///  * `target`: target of the last block of the retarget interval
+    ///  * `timespan`: clamped timespan of the retarget interval
+    fn retarget(target: U256, timespan: u32) -> Result<U256, Error> {
+        // targets have at least 32 leading zero bits so the product fits
+        // for any u32 timespan, but the target comes from a header
+        let new_target = target
+            .checked_mul(U256::from(timespan))
+            .ok_or(Error::ArithmeticOverflow)?
+            / U256::from(TARGET_TIMESPAN);
 
         // ensure target does not exceed max. target
-        match new_target > UNROUNDED_MAX_TARGET {
+        let new_target = match new_target > UNROUNDED_MAX_TARGET {
             true => UNROUNDED_MAX_TARGET,
             false => new_target
         };
 
-        Ok(new_target)
+        // round to the precision of the compact encoding miners use
+        target_from_nbits(nbits_from_target(new_target))
+            .map_err(|_| Error::DiffTargetHeader)
+    }
+
+    /// Clamps the timespan of a retarget interval to the configured
+    /// bounds around TARGET_TIMESPAN
+    /// # Arguments
+    ///  * `timespan`: actual timespan of the retarget interval
+    fn clamp_timespan(timespan: u32) -> u32 {
+        let min_timespan = Self::get_retarget_min_factor() * TARGET_TIMESPAN;
+        let max_timespan = TARGET_TIMESPAN.saturating_mul(Self::get_retarget_max_factor());
+        timespan.max(min_timespan).min(max_timespan)
     }
 
     /// Returns the timestamp of the last difficulty retarget on the specified BlockChain, given the current block height
@@ -701,8 +1697,15 @@ impl<T: Trait> Module<T> {
     /// * `chain_ref` - BlockChain identifier
     /// * `block_height` - current block height
     fn get_last_retarget_time(chain_ref: u32, block_height: u32) -> Result<u64, Error> {
-        let block_chain = Self::get_block_chain_from_id(chain_ref);          
-        let last_retarget_header = Self::get_block_header_from_height(&block_chain, block_height - DIFFICULTY_ADJUSTMENT_INTERVAL)?;
+        let retarget_height = block_height
+            .checked_sub(DIFFICULTY_ADJUSTMENT_INTERVAL)
+            .ok_or(Error::MissingBlockHeight)?;
+        let mut block_chain = Self::get_block_chain_from_id(chain_ref);
+        // forks share the blocks below their start height with the main chain
+        if retarget_height < block_chain.start_height && chain_ref != MAIN_CHAIN_ID {
+            block_chain = Self::get_block_chain_from_id(MAIN_CHAIN_ID);
+        }
+        let last_retarget_header = Self::get_block_header_from_height(&block_chain, retarget_height)?;
         Ok(last_retarget_header.block_header.timestamp)
     }
 
@@ -721,6 +1724,9 @@ impl<T: Trait> Module<T> {
         // load the main chain
         let mut main_chain = Self::get_block_chain_from_id(MAIN_CHAIN_ID);
 
+        // the tip of the main chain before the reorg
+        let old_best_block = Self::get_best_block();
+
         // the start height of the fork
         let start_height = fork.start_height;
 
@@ -732,27 +1738,10 @@ impl<T: Trait> Module<T> {
         // split off the chain
         let forked_chain = main_chain.chain.split_off(&start_height);
 
-        // maybe split off the no data elements
-        // check if there is a no_data block element
-        // that is greater than start_height
-        let index_no_data = main_chain.no_data
-            .iter()
-            .position(|&h| h >= start_height)
-            .map(|v| v as u32);
-        let no_data = match index_no_data {
-            Some(index) => main_chain.no_data.split_off(&index),
-            None => BTreeSet::new(),
-        };
-
-        // maybe split off the invalid elements
-        let index_invalid = main_chain.invalid
-            .iter()
-            .position(|&h| h >= start_height)
-            .map(|v| v as u32);
-        let invalid = match index_invalid {
-            Some(index) => main_chain.invalid.split_off(&index),
-            None => BTreeSet::new(),
-        };
+        // split off the no data and invalid elements
+        // that are greater or equal than start_height
+        let no_data = main_chain.no_data.split_off(&start_height);
+        let invalid = main_chain.invalid.split_off(&start_height);
 
         // store the main chain part that is going to be replaced by the new fork
         // into the forked_main_chain element
@@ -763,11 +1752,13 @@ impl<T: Trait> Module<T> {
             max_height: main_chain.max_height,
             no_data: no_data,
             invalid: invalid,
+            total_work: main_chain.total_work,
         };
 
         // append the fork to the main chain
         main_chain.chain.append(&mut fork.chain.clone());
         main_chain.max_height = fork.max_height;
+        main_chain.total_work = fork.total_work;
         main_chain.no_data.append(&mut fork.no_data.clone());
         main_chain.invalid.append(&mut fork.invalid.clone());
 
@@ -776,6 +1767,9 @@ impl<T: Trait> Module<T> {
             Some(block) => block,
             None => return Err(Error::BlockNotFound),
         };
+        let best_block_timestamp = Self::get_block_header_from_hash(*best_block)?
+            .block_header
+            .timestamp;
 
         // get the position of the fork in Chains
         let position: u32 = Self::get_chain_position_from_chain_id(fork.chain_id)?;
@@ -786,6 +1780,7 @@ impl<T: Trait> Module<T> {
         // Set BestBlock and BestBlockHeight to the submitted block
         Self::set_best_block(best_block.clone());
         Self::set_best_block_height(main_chain.max_height);
+        Self::set_best_block_timestamp(best_block_timestamp);
 
         // remove the fork from storage
         Self::remove_blockchain_from_chainindex(fork.chain_id);
@@ -811,11 +1806,208 @@ impl<T: Trait> Module<T> {
             Self::mutate_block_header_from_chain_id(&block, MAIN_CHAIN_ID);
         }
 
+        // the flagged blocks that were moved off the main chain
+        // no longer affect the main chain
+        Self::resolve_block_errors(
+            &forked_main_chain, ErrorCode::NoDataBTCRelay,
+            &forked_main_chain.no_data);
+        Self::resolve_block_errors(
+            &forked_main_chain, ErrorCode::InvalidBTCRelay,
+            &forked_main_chain.invalid);
+
+        let fork_depth = fork.max_height - fork.start_height;
+        Self::set_last_reorg_depth(fork_depth);
+
+        // announce the new main chain
+        Self::deposit_event(Event::ChainReorg(
+            *best_block,
+            main_chain.max_height,
+            fork_depth,
+            old_best_block,
+        ));
+
+        // announce the orphaned blocks in pages of at most MAX_PAGE_SIZE
+        let orphaned_blocks: Vec<(&u32, &H256Le)> = forked_chain.iter().collect();
+        for page in orphaned_blocks.chunks(MAX_PAGE_SIZE as usize) {
+            Self::deposit_event(Event::BlocksOrphaned(
+                *page[0].0,
+                page.iter().map(|(_height, block)| **block).collect(),
+            ));
+        }
+
+        // notify dependent pallets about the orphaned blocks
+        T::OnReorg::on_reorg(
+            fork_depth,
+            forked_chain.values().cloned().collect(),
+        );
+
+        Ok(())
+    }
+    /// Checks the inclusion of a transaction like the
+    /// verify_transaction_inclusion dispatchable, without emitting an
+    /// event. Used by the dispatchable and for dry runs via the runtime API.
+    ///
+    /// # Arguments
+    ///
+    /// * `tx_id` - The hash of the transaction to check for
+    /// * `block_height` - The height of the block in which the
+    /// transaction should be included
+    /// * `raw_merkle_proof` - The raw merkle proof as returned by
+    /// bitcoin `gettxoutproof`
+    /// * `confirmations` - The number of confirmations needed to accept
+    /// the proof
+    /// * `insecure` - if set, only the given number of confirmations is
+    /// checked and not the global number of stable confirmations
+    pub fn verify_transaction_inclusion_unsigned(
+        tx_id: H256Le,
+        block_height: u32,
+        raw_merkle_proof: &[u8],
+        confirmations: u32,
+        insecure: bool,
+    ) -> Result<(), Error> {
+        let main_chain = Self::check_inclusion_preconditions(
+            block_height,
+            confirmations,
+            insecure
+        )?;
+
+        let proof_result = Self::verify_merkle_proof(raw_merkle_proof)?;
+        
+        let rich_header = Self::get_block_header_from_height(
+            &main_chain,
+            block_height
+        )?;
+
+        // fail if the transaction hash is invalid
+        ensure!(proof_result.transaction_hash == tx_id,
+                Error::InvalidTxid);
+
+        // fail if the merkle root is invalid
+        ensure!(proof_result.extracted_root == rich_header.block_header.merkle_root,
+                Error::InvalidMerkleProof);
+
+        // fail if the proof is for another block than the stored one
+        ensure!(Self::merkle_proof_block_hash(raw_merkle_proof)? == rich_header.block_hash,
+                Error::InvalidMerkleProof);
+
+        Ok(())
+    }
+
+    /// Checks that transactions in the main chain block at the given
+    /// height can be proven, i.e. that the parachain is running, there
+    /// is no ongoing fork, the block is not flagged and has enough
+    /// confirmations. Returns the main chain.
+    ///
+    /// # Arguments
+    ///
+    /// * `block_height` - height of the block including the transaction
+    /// * `confirmations` - The number of confirmations needed to accept
+    /// the proof
+    /// * `insecure` - if set, only the given number of confirmations is
+    /// checked and not the global number of stable confirmations
+    fn check_inclusion_preconditions(
+        block_height: u32,
+        confirmations: u32,
+        insecure: bool,
+    ) -> Result<BlockChain, Error> {
+        // fail if parachain is not in running state.
+        T::ParachainStatus::ensure_parachain_running()?;
+
+        let best_block_height = Self::get_best_block_height();
+
+        // fail if there is an ongoing fork
+        ensure!(!Self::has_ongoing_fork(best_block_height), Error::OngoingFork);
+
+        // fail if the block height is below the height the relay was
+        // initialized at, e.g. a client passing a default height of 0
+        let main_chain = Self::get_block_chain_from_id(MAIN_CHAIN_ID);
+        ensure!(block_height >= main_chain.start_height,
+                Error::MissingBlockHeight);
+
+        // fail if the block was disputed by a staked relayer
+        ensure!(!main_chain.invalid.contains(&block_height),
+                Error::InvalidBlock);
+        ensure!(!main_chain.no_data.contains(&block_height),
+                Error::NoDataBlock);
+
+        // This call fails if not enough confirmations
+        Self::check_confirmations(
+            best_block_height, 
+            confirmations, 
+            block_height, 
+            insecure)?;
+
+        Ok(main_chain)
+    }
+
+    /// Swaps the main chain with a fork without comparing their work
+    ///
+    /// # Arguments
+    ///
+    /// * `chain_id` - id of a fork branching off the main chain
+    ///
+    /// # Errors
+    /// * `ForkIdNotFound` - if the chain is the main chain or not tracked
+    /// * `BlockNotInMainChain` - if the fork branches off another fork
+    fn force_main_chain(chain_id: u32) -> Result<(), Error> {
+        ensure!(
+            chain_id != MAIN_CHAIN_ID && Self::block_chain_exists(chain_id),
+            Error::ForkIdNotFound
+        );
+        let fork = Self::get_block_chain_from_id(chain_id);
+
+        // the swap replaces the main chain from the fork's start height,
+        // so the fork's parent must be part of the main chain
+        let first_block = Self::get_block_header_from_height(&fork, fork.start_height)?;
+        let parent = Self::get_block_header_from_hash(first_block.block_header.hash_prev_block)?;
+        ensure!(parent.chain_ref == MAIN_CHAIN_ID, Error::BlockNotInMainChain);
+
+        let old_best_block = Self::get_best_block();
+        Self::swap_main_blockchain(&fork)?;
+
+        // keep the demoted chain from taking over again once it is
+        // extended, as it still has more work than the new main chain
+        let demoted_chain_id = Self::get_block_header_from_hash(old_best_block)?.chain_ref;
+        <ForcedOutChains>::insert(demoted_chain_id, true);
+        <ForcedOutChains>::remove(chain_id);
+
         Ok(())
     }
-    /// Checks if a newly inserted fork results in an update to the sorted
-    /// Chains mapping. This happens when the max height of the fork is greater
-    /// than the max height of the previous element in the Chains mapping.
+    /// Emits a BlockErrorResolved event for each flagged block height
+    /// that was removed from the main chain by a reorg.
+    ///
+    /// # Arguments
+    ///
+    /// * `blockchain` - the blockchain element now holding the blocks
+    /// * `error` - the error code the blocks were flagged with
+    /// * `heights` - the flagged block heights
+    fn resolve_block_errors(
+        blockchain: &BlockChain,
+        error: ErrorCode,
+        heights: &BTreeSet<u32>,
+    ) {
+        for height in heights.iter() {
+            if let Some(block_hash) = blockchain.chain.get(height) {
+                Self::deposit_event(Event::BlockErrorResolved(
+                    *block_hash,
+                    blockchain.chain_id,
+                    error.clone(),
+                    ErrorResolution::Reorg,
+                ));
+            }
+        }
+    }
+    /// Checks if an extended fork results in an update to the sorted
+    /// Chains mapping. The forks are kept sorted by their max height,
+    /// while the main chain at position 0 is the chain with the most
+    /// cumulative work: a fork replaces it once its total work exceeds
+    /// the main chain's work by more than the stable confirmations worth
+    /// of blocks at the difficulty of the main chain tip.
+    /// Ties are resolved in favour of the first-seen chain.
+    ///
+    /// Reorgs moving more than MaxReorgDepth blocks are not executed,
+    /// the fork is kept and a ReorgTooDeep event is emitted instead.
+    /// Chains forced out of the main chain by root never cause a reorg.
     ///
     /// # Arguments
     ///
@@ -828,15 +2020,12 @@ impl<T: Trait> Module<T> {
         }
 
         // get the position of the fork in Chains
-        let fork_position: u32 = Self::get_chain_position_from_chain_id(
+        let mut current_position: u32 = Self::get_chain_position_from_chain_id(
             fork.chain_id)?;
-        // print!("fork position {:?}\n", fork_position);
-        // check if the previous element in Chains has a lower block_height
-        let mut current_position = fork_position;
-        let mut current_height = fork.max_height;
 
-        // swap elements as long as previous block height is smaller
-        while current_position > 0 {
+        // swap forks as long as the previous fork's height is smaller
+        // NOTE: the main chain at position 0 is never swapped here
+        while current_position > 1 {
             // get the previous position
             let prev_position = current_position - 1;
             // get the blockchain id
@@ -845,125 +2034,158 @@ impl<T: Trait> Module<T> {
             // get the previous blockchain height
             let prev_height = Self::get_block_chain_from_id(prev_blockchain_id)
                 .max_height;
-            // swap elements if block height is greater
-            // print!("curr height {:?}\n", current_height);
-            // print!("prev height {:?}\n", prev_height);
-            if prev_height < current_height {
-                // Check if swap occurs on the main chain element
-                // print!("prev chain id {:?}\n", prev_blockchain_id);
-                if prev_blockchain_id == MAIN_CHAIN_ID {
-                    // if the previous position is the top element
-                    // and the current height is more than the
-                    // STABLE_TRANSACTION_CONFIRMATIONS ahead
-                    // we are swapping the main chain
-                    if prev_height + STABLE_TRANSACTION_CONFIRMATIONS 
-                        < current_height {
-                        Self::swap_main_blockchain(&fork)?;
-
-                        // announce the new main chain
-                        let new_chain_tip = <BestBlock>::get();
-                        let block_height = <BestBlockHeight>::get();
-                        let fork_depth = fork.max_height - fork.start_height;
-                        // print!("tip {:?}\n", new_chain_tip);
-                        // print!("block height {:?}\n", block_height);
-                        // print!("depth {:?}\n", fork_depth);
-                        Self::deposit_event(Event::ChainReorg(
-                            new_chain_tip,
-                            block_height,
-                            fork_depth,
-                        ));
-                    } else {
-                        Self::deposit_event(Event::ForkAheadOfMainChain(
-                            prev_height, // main chain height
-                            fork.max_height, // fork height
-                            fork.chain_id, // fork id
-                        ));
-                    }
-                    // break the while loop
-                    break;
-                } else {
-                    // else, simply swap the chain_id ordering in Chains
-                    Self::swap_chain(prev_position, current_position);
-                }
-
-                // update the current chain to the previous one
+            // NOTE: on equal heights the chains keep their order, i.e.
+            // the first-seen chain stays ahead
+            if prev_height < fork.max_height {
+                Self::swap_chain(prev_position, current_position);
                 current_position = prev_position;
-                current_height = prev_height;
             } else {
                 break;
             }
         }
 
+        // a chain forced out of the main chain is kept but not compared
+        if Self::is_forced_out(fork.chain_id) {
+            return Ok(());
+        }
+
+        // compare the work of the fork and the main chain
+        // NOTE: a competing block at the main chain tip with the same
+        // difficulty only creates a fork and never triggers a reorg since
+        // work is equal
+        let main_chain = Self::get_block_chain_from_id(MAIN_CHAIN_ID);
+        if fork.total_work > main_chain.total_work {
+            if fork.total_work > Self::reorg_work_threshold(&main_chain)? {
+                // the number of blocks moved between the main chain and
+                // the fork, bounding the work done by the swap
+                let reorg_depth = main_chain.max_height.max(fork.max_height)
+                    - fork.start_height;
+                if reorg_depth > Self::get_max_reorg_depth() {
+                    Self::deposit_event(Event::ReorgTooDeep(
+                        fork.chain_id,
+                        reorg_depth,
+                    ));
+                } else {
+                    // announces the new main chain
+                    Self::swap_main_blockchain(&fork)?;
+                }
+            } else {
+                Self::deposit_event(Event::ForkAheadOfMainChain(
+                    main_chain.max_height, // main chain height
+                    fork.max_height, // fork height
+                    fork.chain_id, // fork id
+                ));
+            }
+        }
+
         Ok(())
     }
-    /// Insert a new fork into the Chains mapping sorted by its max height
+    /// Returns the fork with the most total work that is not forced out
+    /// of the main chain, if any
+    fn get_heaviest_fork() -> Option<BlockChain> {
+        <Chains>::enumerate()
+            .map(|(_position, chain_id)| chain_id)
+            .filter(|chain_id| *chain_id != MAIN_CHAIN_ID && !Self::is_forced_out(*chain_id))
+            .map(Self::get_block_chain_from_id)
+            .max_by_key(|fork| fork.total_work)
+    }
+    /// Returns the total work a fork must exceed to replace the main
+    /// chain, i.e. the main chain's work plus the work of as many blocks
+    /// as the current stable confirmations at the difficulty of its tip.
+    ///
+    /// # Arguments
+    ///
+    /// * `main_chain` - the current main chain
+    fn reorg_work_threshold(main_chain: &BlockChain) -> Result<U256, Error> {
+        let tip_hash = main_chain
+            .chain
+            .get(&main_chain.max_height)
+            .ok_or(Error::BlockNotFound)?;
+        let tip = Self::get_block_header_from_hash(*tip_hash)?;
+        let stable_work = Self::block_work(tip.block_header.target)
+            .saturating_mul(Self::get_stable_transaction_confirmations().into());
+        Ok(main_chain.total_work.saturating_add(stable_work))
+    }
+    /// Insert a new fork into the Chains mapping sorted by its max height.
+    /// Finding the position takes O(log F) reads of the F tracked forks,
+    /// but the forks behind it are moved down by one position each, i.e.
+    /// inserting still costs up to F reads and 2 * F writes of Chains and
+    /// ChainsPositions.
     ///
     /// # Arguments
     ///
     /// * `blockchain` - new blockchain element
     fn insert_sorted(blockchain: &BlockChain) {
-        // print!("Chain id: {:?}\n", blockchain.chain_id);
-        // get a sorted vector over the Chains elements
-        // NOTE: LinkedStorageMap iterators are not sorted over the keys
-        let mut chains = <Chains>::enumerate().collect::<Vec<(u32, u32)>>();
-        chains.sort_by_key(|k| k.0);
-
-        let max_chain_element = chains.len() as u32;
-        // define the position of the new blockchain
-        // by default, we insert it as the last element
-        let mut position_blockchain = max_chain_element;
+        let max_chain_element = Self::get_chains_length();
 
-        // Starting from the second highest element, find where to insert the new fork
-        // the previous element's block height should be higher or equal
-        // the next element's block height should be lower or equal
         // NOTE: we never want to insert a new main chain through this function
-        for (curr_position, curr_chain_id) in chains.iter().skip(1) {
+        // binary search for the first fork with a height lower or equal
+        // than the new blockchain, by default it is inserted last
+        let mut low = max_chain_element.min(1);
+        let mut high = max_chain_element;
+        while low < high {
+            let mid = low + (high - low) / 2;
             // get the height of the current chain_id
-            let curr_height = Self::get_block_chain_from_id(
-                curr_chain_id.clone())
+            let mid_height = Self::get_block_chain_from_id(
+                Self::get_chain_id_from_position(mid))
                 .max_height;
 
-            // if the height of the current blockchain is lower than
-            // the new blockchain, it should be inserted at that position
-            if curr_height <= blockchain.max_height {
-                position_blockchain = *curr_position;
-                break;
-            };
+            if mid_height <= blockchain.max_height {
+                high = mid;
+            } else {
+                low = mid + 1;
+            }
         }
+        // define the position of the new blockchain
+        let position_blockchain = low;
 
-        // insert the new fork into the chains element
-        Self::set_chain_from_position_and_id(
-            max_chain_element, blockchain.chain_id);
-        // starting from the last element swap the positions until
-        // the new blockchain is at the position_blockchain
-        // print!("max element {:?}\n", max_chain_element);
-        // print!("position blockchain {:?}\n", position_blockchain);
-        for curr_position in (position_blockchain+1..max_chain_element+1).rev() {
-            // stop when the blockchain element is at it's
-            // designated position
-            // print!("current position {:?}\n", curr_position);
-            if curr_position < position_blockchain {
-                break;
-            };
-            let prev_position = curr_position - 1;
-            // swap the current element with the previous one
-            // print!("Swapping pos {:?} with pos {:?}\n", curr_position, prev_position);
-            Self::swap_chain(curr_position, prev_position);
+        // starting from the last element move the forks behind the new
+        // position down by one, overwriting instead of swapping writes
+        // each moved fork once
+        for curr_position in (position_blockchain + 1..max_chain_element + 1).rev() {
+            Self::move_chain_to_position(curr_position, <Chains>::get(curr_position - 1));
         }
+        Self::move_chain_to_position(position_blockchain, blockchain.chain_id);
+
+        debug_assert!(
+            Self::chains_are_sorted(),
+            "Chains is not sorted by max height after insert_sorted"
+        );
+    }
+    /// Checks if the forks in Chains are sorted descending by their max
+    /// height. The main chain at position 0 is excepted.
+    fn chains_are_sorted() -> bool {
+        let mut chains = <Chains>::enumerate().collect::<Vec<(u32, u32)>>();
+        chains.sort_by_key(|k| k.0);
+
+        let heights = chains
+            .iter()
+            .skip(1)
+            .map(|(_position, id)| Self::get_block_chain_from_id(*id).max_height)
+            .collect::<Vec<u32>>();
+
+        heights.windows(2).all(|w| w[0] >= w[1])
     }
     /// Flag an error in a block header. This function is called by the 
-    /// security pallet.
+    /// security pallet. Flagging a block twice with the same error is a
+    /// no-op, so a single clear removes the flag.
     ///
     /// # Arguments
     ///
+    /// * `relayer` - the staked relayer reporting the error
     /// * `block_hash` - the hash of the block header with the error
     /// * `error` - the error code for the block header
-    pub fn flag_block_error(block_hash: H256Le, error: ErrorCode) 
+    pub fn flag_block_error(relayer: T::AccountId, block_hash: H256Le, error: ErrorCode) 
     -> Result<(), Error> {
+        T::StakedRelayers::ensure_staked_relayer(&relayer)?;
+
         // Get the chain id of the block header
         let block_header = Self::get_block_header_from_hash(block_hash)?;
         let chain_id = block_header.chain_ref;
 
+        // The chain_ref may be stale if the chain was removed by a reorg
+        ensure!(Self::block_chain_exists(chain_id), Error::ForkIdNotFound);
+
         // Get the blockchain element for the chain id
         let mut blockchain = Self::get_block_chain_from_id(chain_id);
 
@@ -993,20 +2215,26 @@ impl<T: Trait> Module<T> {
     ///
     /// # Arguments
     ///
+    /// * `relayer` - the staked relayer reporting the error
     /// * `block_hash` - the hash of the block header being cleared
     /// * `error` - the error code for the block header
-    pub fn clear_block_error(block_hash: H256Le, error: ErrorCode) 
+    pub fn clear_block_error(relayer: T::AccountId, block_hash: H256Le, error: ErrorCode) 
     -> Result<(), Error> {
+        T::StakedRelayers::ensure_staked_relayer(&relayer)?;
+
         // Get the chain id of the block header
         let block_header = Self::get_block_header_from_hash(block_hash)?;
         let chain_id = block_header.chain_ref;
 
+        // The chain_ref may be stale if the chain was removed by a reorg
+        ensure!(Self::block_chain_exists(chain_id), Error::ForkIdNotFound);
+
         // Get the blockchain element for the chain id
         let mut blockchain = Self::get_block_chain_from_id(chain_id);
 
         // Clear errors in the blockchain entry
         // Check which error we are dealing with
-        let block_exists = match error {
+        let block_flagged = match error {
             ErrorCode::NoDataBTCRelay => {
                 blockchain.no_data.remove(&block_header.block_height)
             },
@@ -1016,20 +2244,22 @@ impl<T: Trait> Module<T> {
             _ => return Err(Error::UnknownErrorcode),
         };
 
-        if block_exists {
-            // Store the updated blockchain entry
-            Self::mutate_block_chain_from_id(chain_id, blockchain);
+        // fail if the block was never flagged or was already cleared
+        ensure!(block_flagged, Error::BlockNotFlagged);
 
-            Self::deposit_event(
-                Event::ClearBlockError(block_hash, chain_id, error)
-            );
-        }
+        // Store the updated blockchain entry
+        Self::mutate_block_chain_from_id(chain_id, blockchain);
+
+        Self::deposit_event(
+            Event::ClearBlockError(block_hash, chain_id, error)
+        );
 
         Ok (())
     }
 
     /// Checks if the given transaction confirmations are greater/equal to the
-    /// requested confirmations (and/or the global k security parameter)
+    /// requested confirmations (and/or the global k security parameter).
+    /// The block including the transaction counts as the first confirmation.
     /// 
     /// # Arguments
     /// * `block_height` - current main chain block height
@@ -1038,9 +2268,13 @@ impl<T: Trait> Module<T> {
     /// * `insecure` -  determines if checks against recommended global transaction confirmation are to be executed. Recommended: set to `true` 
     /// 
     pub fn check_confirmations(main_chain_height: u32, req_confs: u32, tx_block_height: u32, insecure: bool) -> Result<(),Error> {
+        let confirmations = main_chain_height
+            .checked_sub(tx_block_height)
+            .map_or(0, |depth| depth.saturating_add(1));
+
         // insecure call: only checks against user parameter
         if insecure {
-            match  tx_block_height + req_confs <= main_chain_height {
+            match confirmations >= req_confs {
                 true => Ok(()),
                 false => Err(Error::Confirmations)
             }
@@ -1049,18 +2283,854 @@ impl<T: Trait> Module<T> {
             let global_confs = Self::get_stable_transaction_confirmations();
 
             if global_confs > req_confs {
-                match tx_block_height + global_confs <= main_chain_height {
+                match confirmations >= global_confs {
                     true => Ok(()),
                     false => Err(Error::InsufficientStableConfirmations)
                 }
             } else {
-                match tx_block_height + req_confs <= main_chain_height {
+                match confirmations >= req_confs {
                     true => Ok(()),
                     false => Err(Error::Confirmations)
                 }
-            } 
+            }
         }
     }
+
+    /// Removes all forks with a tip at or below `up_to_height` that are
+    /// more than PruningWindow blocks behind the main chain tip, together
+    /// with their block headers. Once MAX_PRUNED_HEADERS headers are
+    /// removed, the next stale fork is shortened from its tip and the
+    /// remaining forks are kept. The remaining chains keep their order
+    /// in Chains. The tips of the removed headers are kept in
+    /// PrunedForks.
+    ///
+    /// # Arguments
+    /// * `up_to_height` - maximum tip height of the pruned forks
+    ///
+    /// # Returns
+    /// * the number of removed block headers
+    fn prune_stale_chains(up_to_height: u32) -> Result<u32, Error> {
+        let stale_height = Self::get_best_block_height()
+            .saturating_sub(Self::get_pruning_window());
+
+        // NOTE: LinkedStorageMap iterators are not sorted over the keys
+        let mut chains = <Chains>::enumerate().collect::<Vec<(u32, u32)>>();
+        chains.sort_by_key(|k| k.0);
+
+        let mut pruned: u32 = 0;
+        // the ids and tip heights of the chains that are kept
+        let mut retained: Vec<(u32, u32)> = Vec::new();
+        // the tip hashes and heights of the removed headers of each fork
+        let mut pruned_tips: Vec<(H256Le, u32)> = Vec::new();
+        for (_position, chain_id) in chains.iter() {
+            let mut blockchain = Self::get_block_chain_from_id(*chain_id);
+            let budget = MAX_PRUNED_HEADERS - pruned;
+            if *chain_id == MAIN_CHAIN_ID
+                || blockchain.max_height > up_to_height
+                || blockchain.max_height >= stale_height
+                || budget == 0
+            {
+                retained.push((*chain_id, blockchain.max_height));
+                continue;
+            }
+
+            let tip = *blockchain.chain.get(&blockchain.max_height)
+                .ok_or(Error::BlockNotFound)?;
+            pruned_tips.push((tip, blockchain.max_height));
+
+            if blockchain.chain.len() as u32 <= budget {
+                for (_height, block_hash) in blockchain.chain.iter() {
+                    <BlockHeaders>::remove(block_hash);
+                    <WitnessRoots>::remove(block_hash);
+                }
+                pruned += blockchain.chain.len() as u32;
+                Self::remove_blockchain_from_chainindex(*chain_id);
+                <ForcedOutChains>::remove(*chain_id);
+            } else {
+                // shorten the fork to fit the remaining budget
+                let max_height = blockchain.max_height - budget;
+                let removed = blockchain.chain.split_off(&(max_height + 1));
+                for (_height, block_hash) in removed.iter() {
+                    let target = Self::get_block_header_from_hash(*block_hash)?
+                        .block_header
+                        .target;
+                    blockchain.total_work = blockchain
+                        .total_work
+                        .saturating_sub(Self::block_work(target));
+                    <BlockHeaders>::remove(block_hash);
+                    <WitnessRoots>::remove(block_hash);
+                }
+                blockchain.no_data.retain(|height| *height <= max_height);
+                blockchain.invalid.retain(|height| *height <= max_height);
+                blockchain.max_height = max_height;
+                Self::set_block_chain_from_id(*chain_id, &blockchain);
+                pruned += budget;
+                retained.push((*chain_id, max_height));
+            }
+        }
+
+        if pruned == 0 {
+            return Ok(0);
+        }
+
+        // only remember the most recently pruned forks
+        <PrunedForks>::mutate(|pruned_forks| {
+            pruned_forks.extend(pruned_tips);
+            let excess = pruned_forks.len().saturating_sub(MAX_PRUNED_FORKS);
+            pruned_forks.drain(..excess);
+        });
+
+        // a shortened fork may have to move back, the main chain stays
+        // at position 0 and forks of equal height keep their order
+        retained[1..].sort_by_key(|(_chain_id, max_height)| sp_std::cmp::Reverse(*max_height));
+
+        // close the gaps left by the removed forks
+        for (position, _chain_id) in chains.iter() {
+            Self::remove_chain_from_position(*position);
+        }
+        for (position, (chain_id, _max_height)) in retained.iter().enumerate() {
+            Self::set_chain_from_position_and_id(position as u32, *chain_id);
+        }
+
+        Ok(pruned)
+    }
+
+    /// Removes the oldest block headers of the main chain, moving up its
+    /// start height. Headers are kept if they are less than the retained
+    /// depth below the tip, above `up_to_height`, at or above the lowest
+    /// height referenced by a dependent pallet, or the parent of a fork.
+    ///
+    /// # Arguments
+    /// * `up_to_height` - maximum height of the pruned headers
+    /// * `budget` - maximum number of pruned headers
+    ///
+    /// # Returns
+    /// * the number of removed block headers
+    fn prune_main_chain(up_to_height: u32, budget: u32) -> Result<u32, Error> {
+        let mut main_chain = Self::get_block_chain_from_id(MAIN_CHAIN_ID);
+
+        // the height of the lowest header that is kept
+        let mut keep_height = Self::get_best_block_height()
+            .saturating_sub(Self::get_retained_depth())
+            .min(up_to_height.saturating_add(1))
+            .min(main_chain.start_height.saturating_add(budget));
+        if let Some(height) = T::PruningFilter::lowest_referenced_height() {
+            keep_height = keep_height.min(height);
+        }
+        // forks need their parent on the main chain
+        for (_position, chain_id) in <Chains>::enumerate() {
+            if chain_id != MAIN_CHAIN_ID {
+                let fork = Self::get_block_chain_from_id(chain_id);
+                keep_height = keep_height.min(fork.start_height.saturating_sub(1));
+            }
+        }
+
+        if keep_height <= main_chain.start_height {
+            return Ok(0);
+        }
+
+        let kept = main_chain.chain.split_off(&keep_height);
+        for (_height, block_hash) in main_chain.chain.iter() {
+            <BlockHeaders>::remove(block_hash);
+            <WitnessRoots>::remove(block_hash);
+        }
+        let pruned = main_chain.chain.len() as u32;
+
+        // the total work stays the cumulative work of the chain
+        main_chain.chain = kept;
+        main_chain.no_data = main_chain.no_data.split_off(&keep_height);
+        main_chain.invalid = main_chain.invalid.split_off(&keep_height);
+        main_chain.start_height = keep_height;
+        Self::set_block_chain_from_id(MAIN_CHAIN_ID, &main_chain);
+
+        Ok(pruned)
+    }
+
+    /// Checks if any fork is within the stable confirmations of the main
+    /// chain tip, i.e. transactions close to the tip could still be
+    /// reorged out of the main chain. Chains forced out of the main
+    /// chain are ignored since they cannot cause a reorg.
+    ///
+    /// # Arguments
+    /// * `best_block_height` - current main chain block height
+    fn has_ongoing_fork(best_block_height: u32) -> bool {
+        let stable_confs = Self::get_stable_transaction_confirmations();
+        <Chains>::enumerate()
+            .filter(|(_position, chain_id)| {
+                *chain_id != MAIN_CHAIN_ID && !Self::is_forced_out(*chain_id)
+            })
+            .any(|(_position, chain_id)| {
+                Self::get_block_chain_from_id(chain_id)
+                    .max_height
+                    .saturating_add(stable_confs) > best_block_height
+            })
+    }
+
+    /// Checks if an output script pays to the given recipient, using the
+    /// same output classifier as `validate_transaction`. Exposed through
+    /// the runtime API so wallets can check an output before broadcasting.
+    ///
+    /// # Arguments
+    /// * `script` - raw output script (scriptPubKey)
+    /// * `recipient` - address hash (or witness program) of the recipient
+    /// * `_network` - Bitcoin network of the address. Script hashes do not
+    /// encode the network, so it does not affect the comparison.
+    pub fn script_matches_recipient(
+        script: Vec<u8>,
+        recipient: Vec<u8>,
+        _network: BitcoinNetwork,
+    ) -> bool {
+        if script.is_empty() {
+            return false;
+        }
+        match extract_address_hash(&script) {
+            Ok(extr_recipient) => extr_recipient == recipient,
+            Err(_) => false,
+        }
+    }
+
+    /// Verifies the inclusion of `tx_id` in a small (one or two
+    /// transaction) main chain block from all raw transactions of the
+    /// block, without the partial merkle tree proof format.
+    ///
+    /// The relay only stores headers, so the number of transactions of
+    /// the block is proven by recomputing the merkle root from the
+    /// parsed transactions. Transactions of MERKLE_INNER_NODE_SIZE bytes
+    /// are rejected, so that inner nodes of a larger block cannot be
+    /// passed off as its transactions. The same status, flag and
+    /// confirmation checks as in verify_transaction_inclusion apply.
+    ///
+    /// # Arguments
+    /// * `tx_id` - hash of the transaction
+    /// * `block_height` - height of the block in the main chain
+    /// * `raw_transactions` - all raw transactions of the block in block
+    /// order, without witness data
+    /// * `confirmations` - The number of confirmations needed to accept
+    /// the proof
+    /// * `insecure` - if set, only the given number of confirmations is
+    /// checked and not the global number of stable confirmations
+    pub fn verify_small_block_inclusion(
+        tx_id: H256Le,
+        block_height: u32,
+        raw_transactions: Vec<Vec<u8>>,
+        confirmations: u32,
+        insecure: bool,
+    ) -> Result<(), Error> {
+        ensure!(
+            !raw_transactions.is_empty()
+                && raw_transactions.len() <= MAX_SMALL_BLOCK_TRANSACTIONS,
+            Error::InvalidMerkleProof
+        );
+
+        let main_chain = Self::check_inclusion_preconditions(
+            block_height,
+            confirmations,
+            insecure
+        )?;
+
+        let mut transactions = Vec::new();
+        for raw_tx in raw_transactions.iter() {
+            let transaction = Self::parse_transaction(raw_tx)?;
+            ensure!(
+                transaction.to_legacy_bytes().len() != MERKLE_INNER_NODE_SIZE,
+                Error::TxFormat
+            );
+            transactions.push(transaction);
+        }
+
+        let rich_header = Self::get_block_header_from_height(
+            &main_chain,
+            block_height
+        )?;
+
+        verify_block_merkle_root(&rich_header.block_header, &transactions)
+            .map_err(|_e| Error::InvalidMerkleProof)?;
+
+        ensure!(
+            transactions
+                .iter()
+                .any(|transaction| Transaction::tx_id(&transaction.to_legacy_bytes()) == tx_id),
+            Error::InvalidTxid
+        );
+
+        Ok(())
+    }
+
+    /// Proves the inclusion of the coinbase transaction in a stored block
+    /// and returns the block height committed in it (BIP34). Fails if the
+    /// committed height does not match the height of the stored header.
+    ///
+    /// # Arguments
+    /// * `block_hash` - hash of the stored block header
+    /// * `coinbase_tx` - raw coinbase transaction (without witness data)
+    /// * `coinbase_proof` - raw merkle proof of the coinbase transaction
+    pub fn get_coinbase_height(
+        block_hash: H256Le,
+        coinbase_tx: Vec<u8>,
+        coinbase_proof: Vec<u8>,
+    ) -> Result<u32, Error> {
+        let rich_header = Self::get_block_header_from_hash(block_hash)?;
+
+        let proof_result = Self::verify_merkle_proof(&coinbase_proof)?;
+
+        // the coinbase is always the first transaction in the block
+        ensure!(proof_result.transaction_position == 0,
+                Error::InvalidMerkleProof);
+        ensure!(proof_result.extracted_root == rich_header.block_header.merkle_root,
+                Error::InvalidMerkleProof);
+        ensure!(proof_result.transaction_hash == Transaction::tx_id(&coinbase_tx),
+                Error::InvalidTxid);
+
+        let transaction = Self::parse_transaction(&coinbase_tx)?;
+        let coinbase_input = transaction.inputs.get(0).ok_or(Error::TxFormat)?;
+        let height = extract_coinbase_height(coinbase_input)
+            .map_err(|_e| Error::TxFormat)?;
+
+        ensure!(height == rich_header.block_height, Error::Bip34HeightMismatch);
+
+        Ok(height)
+    }
+
+    /// Checks that the coinbase transaction of a stored block commits to
+    /// the given witness merkle root (BIP141).
+    ///
+    /// # Arguments
+    /// * `block_hash` - hash of the stored block header
+    /// * `coinbase_tx` - raw coinbase transaction
+    /// * `coinbase_proof` - raw merkle proof of the coinbase transaction
+    /// * `witness_root` - witness merkle root of the block
+    /// * `witness_reserved_value` - witness reserved value of the
+    /// coinbase input
+    pub fn verify_witness_root(
+        block_hash: H256Le,
+        coinbase_tx: Vec<u8>,
+        coinbase_proof: Vec<u8>,
+        witness_root: H256Le,
+        witness_reserved_value: H256Le,
+    ) -> Result<(), Error> {
+        let outputs = Self::parse_transaction_outputs(&coinbase_tx)?;
+        Self::get_coinbase_height(block_hash, coinbase_tx, coinbase_proof)?;
+
+        // if several outputs match, the last one is the commitment
+        let commitment = outputs
+            .iter()
+            .rev()
+            .map(|output| &output.script)
+            .find(|script| script.len() >= WITNESS_COMMITMENT_HEADER.len() + 32
+                && script.starts_with(&WITNESS_COMMITMENT_HEADER))
+            .map(|script| {
+                let start = WITNESS_COMMITMENT_HEADER.len();
+                &script[start..start + 32]
+            })
+            .ok_or(Error::InvalidWitnessCommitment)?;
+
+        let mut preimage = witness_root.to_bytes_le().to_vec();
+        preimage.extend_from_slice(&witness_reserved_value.to_bytes_le());
+        ensure!(sha256d(&preimage)[..] == commitment[..], Error::InvalidWitnessCommitment);
+
+        Ok(())
+    }
+
+    /// Verifies the inclusion of a transaction in the main chain and that
+    /// one of its inputs spends the given output. Lets a staked relayer
+    /// prove that an output a vault claims to have spent otherwise was
+    /// spent by a competing transaction. Spending transactions of
+    /// MERKLE_INNER_NODE_SIZE bytes are rejected.
+    ///
+    /// # Arguments
+    /// * `outpoint_txid` - id of the transaction holding the spent output
+    /// * `outpoint_index` - index of the spent output
+    /// * `raw_spending_tx` - raw transaction spending the output
+    /// * `block_height` - height of the block including the spending
+    /// transaction
+    /// * `raw_merkle_proof` - raw merkle proof of the spending transaction
+    /// * `confirmations` - number of confirmations needed to accept the proof
+    /// * `insecure` - if set, only the given number of confirmations is
+    /// checked and not the global number of stable confirmations
+    pub fn verify_output_spent(
+        outpoint_txid: H256Le,
+        outpoint_index: u32,
+        raw_spending_tx: &[u8],
+        block_height: u32,
+        raw_merkle_proof: &[u8],
+        confirmations: u32,
+        insecure: bool,
+    ) -> Result<(), Error> {
+        let transaction = Self::parse_transaction(raw_spending_tx)?;
+        // an inner node of the merkle tree could be passed off as a
+        // transaction of this size
+        ensure!(
+            transaction.to_legacy_bytes().len() != MERKLE_INNER_NODE_SIZE,
+            Error::TxFormat
+        );
+        let tx_id = Self::extract_tx_id(raw_spending_tx)?;
+
+        Self::verify_transaction_inclusion_unsigned(
+            tx_id,
+            block_height,
+            raw_merkle_proof,
+            confirmations,
+            insecure
+        )?;
+
+        ensure!(
+            transaction.inputs.iter().any(|input| input.spends(&outpoint_txid, outpoint_index)),
+            Error::OutputNotSpent
+        );
+
+        Ok(())
+    }
+
+    /// Returns the depth of a main chain block below the tip, i.e. the
+    /// number of confirmations of the block (the tip has depth 1).
+    ///
+    /// # Arguments
+    /// * `block_hash` - hash of the block header
+    pub fn block_depth(block_hash: H256Le) -> Result<u32, Error> {
+        let rich_header = Self::get_block_header_from_hash(block_hash)?;
+        let best_block_height = Self::get_best_block_height();
+
+        ensure!(rich_header.chain_ref == MAIN_CHAIN_ID
+                && rich_header.block_height <= best_block_height,
+                Error::BlockNotInMainChain);
+
+        Ok(best_block_height - rich_header.block_height + 1)
+    }
+
+    /// Returns the number of main chain blocks stored on top of a block,
+    /// or `None` if the block is unknown or not part of the main chain.
+    /// Lets a relayer detect if the last block it submitted is stale.
+    ///
+    /// # Arguments
+    /// * `block_hash` - hash of the block header
+    pub fn blocks_behind_tip(block_hash: H256Le) -> Option<u32> {
+        Self::block_depth(block_hash).ok().map(|depth| depth - 1)
+    }
+
+    /// Checks if a block is the tip of the main chain
+    ///
+    /// # Arguments
+    /// * `block_hash` - hash of the block header
+    pub fn is_block_main_chain_tip(block_hash: H256Le) -> bool {
+        Self::best_block_exists() && Self::get_best_block() == block_hash
+    }
+
+    /// Returns the id of the chain a block is stored on, i.e.
+    /// MAIN_CHAIN_ID or the id of a fork
+    ///
+    /// # Arguments
+    /// * `block_hash` - hash of the block header
+    pub fn get_chain_ref_for_block(block_hash: H256Le) -> Result<u32, Error> {
+        Self::get_block_header_from_hash(block_hash)
+            .map(|rich_header| rich_header.chain_ref)
+    }
+
+    /// Checks if a block is part of the main chain
+    ///
+    /// # Arguments
+    /// * `block_hash` - hash of the block header
+    pub fn is_block_canonical(block_hash: H256Le) -> bool {
+        Self::get_chain_ref_for_block(block_hash) == Ok(MAIN_CHAIN_ID)
+    }
+
+    /// Checks if the relay was initialized with its first block, i.e. if
+    /// block headers can be stored
+    pub fn is_fully_initialized() -> bool {
+        Self::best_block_exists()
+    }
+
+    /// Returns the block header at the given height of a tracked chain.
+    ///
+    /// # Arguments
+    /// * `chain_id` - id of the chain
+    /// * `block_height` - height of the block header
+    ///
+    /// # Errors
+    /// * `ForkIdNotFound` - if the chain is not tracked
+    /// * `MissingBlockHeight` - if the chain has no block at this height
+    /// * `BlockNotFound` - if no header is stored for the block hash
+    pub fn get_block_header_at_height(
+        chain_id: u32,
+        block_height: u32
+    ) -> Result<RichBlockHeader, Error> {
+        ensure!(Self::block_chain_exists(chain_id), Error::ForkIdNotFound);
+        Self::get_block_header_from_height(
+            &Self::get_block_chain_from_id(chain_id),
+            block_height
+        )
+    }
+
+    /// Returns the compact nBits target of the main chain block at the
+    /// given height.
+    ///
+    /// # Arguments
+    /// * `block_height` - height of the main chain block
+    pub fn get_bits_at_height(block_height: u32) -> Result<u32, Error> {
+        Self::get_block_header_at_height(MAIN_CHAIN_ID, block_height)
+            .map(|rich_header| rich_header.block_header.bits)
+    }
+
+    /// Returns the height and hash of the tip of a tracked chain, or
+    /// `None` if the chain is not tracked.
+    ///
+    /// # Arguments
+    /// * `chain_id` - id of the chain
+    pub fn chain_tip_for(chain_id: u32) -> Option<(u32, H256Le)> {
+        if !Self::block_chain_exists(chain_id) {
+            return None;
+        }
+        let blockchain = Self::get_block_chain_from_id(chain_id);
+        Self::get_block_hash(&blockchain, blockchain.max_height)
+            .ok()
+            .map(|hash| (blockchain.max_height, hash))
+    }
+
+    /// Returns the hash of the main chain block at the given height, or
+    /// `None` if the height is outside the stored main chain.
+    ///
+    /// # Arguments
+    /// * `block_height` - height of the main chain block
+    pub fn get_main_chain_block_hash(block_height: u32) -> Option<H256Le> {
+        let main_chain = Self::get_block_chain_from_id(MAIN_CHAIN_ID);
+        if block_height < main_chain.start_height || block_height > main_chain.max_height {
+            return None;
+        }
+        Self::get_block_hash(&main_chain, block_height).ok()
+    }
+
+    /// Returns the heights and hashes of up to `count` consecutive main
+    /// chain blocks starting at `from_height`, clamped to the stored main
+    /// chain. Lets a restarted relayer find where to resume.
+    ///
+    /// # Arguments
+    /// * `from_height` - height of the first block
+    /// * `count` - maximum number of blocks to return, bounded by
+    /// MAX_PAGE_SIZE
+    pub fn get_main_chain_hashes(from_height: u32, count: u32) -> Vec<(u32, H256Le)> {
+        let main_chain = Self::get_block_chain_from_id(MAIN_CHAIN_ID);
+        if from_height > main_chain.max_height {
+            return Vec::new();
+        }
+        main_chain
+            .chain
+            .range(from_height..=main_chain.max_height)
+            .take(Self::page_size(count))
+            .map(|(height, hash)| (*height, *hash))
+            .collect()
+    }
+
+    /// Returns all tracked forks, i.e. all chains except the main chain,
+    /// as `(position, chain_id, max_height)` sorted by position.
+    pub fn get_ongoing_forks() -> Vec<(u32, u32, u32)> {
+        // NOTE: LinkedStorageMap iterators are not sorted over the keys
+        let mut chains = <Chains>::enumerate().collect::<Vec<(u32, u32)>>();
+        chains.sort_by_key(|k| k.0);
+
+        chains
+            .into_iter()
+            .filter(|(_position, chain_id)| *chain_id != MAIN_CHAIN_ID)
+            .map(|(position, chain_id)| {
+                (position, chain_id, Self::get_block_chain_from_id(chain_id).max_height)
+            })
+            .collect()
+    }
+
+    /// Returns a page of the block headers at the given height of all
+    /// tracked chains, ordered by the position of the chains in Chains.
+    ///
+    /// # Arguments
+    /// * `block_height` - height of the block headers
+    /// * `from` - number of matching headers to skip
+    /// * `limit` - maximum number of headers to return, bounded by
+    /// MAX_PAGE_SIZE
+    pub fn headers_at_height(block_height: u32, from: u32, limit: u32) -> Vec<RichBlockHeader> {
+        // NOTE: LinkedStorageMap iterators are not sorted over the keys
+        let mut chains = <Chains>::enumerate().collect::<Vec<(u32, u32)>>();
+        chains.sort_by_key(|k| k.0);
+
+        chains
+            .iter()
+            .filter_map(|(_position, chain_id)| {
+                Self::get_block_header_from_height(
+                    &Self::get_block_chain_from_id(*chain_id),
+                    block_height
+                ).ok()
+            })
+            .skip(from as usize)
+            .take(Self::page_size(limit))
+            .collect()
+    }
+
+    /// Returns a page of the block headers of a chain in height order,
+    /// starting at the chain's start height. Intended for diagnostics.
+    ///
+    /// # Arguments
+    /// * `chain_id` - id of the chain to dump
+    /// * `from` - number of headers to skip from the start of the chain
+    /// * `limit` - maximum number of headers to return, bounded by
+    /// MAX_PAGE_SIZE
+    pub fn dump_chain(chain_id: u32, from: u32, limit: u32) -> Vec<RichBlockHeader> {
+        Self::get_block_chain_from_id(chain_id)
+            .chain
+            .values()
+            .skip(from as usize)
+            .take(Self::page_size(limit))
+            .filter_map(|hash| Self::get_block_header_from_hash(*hash).ok())
+            .collect()
+    }
+
+    /// Clamps a requested page size to MAX_PAGE_SIZE
+    fn page_size(limit: u32) -> usize {
+        limit.min(MAX_PAGE_SIZE) as usize
+    }
+
+    /// Returns the payload pushed by an OP_RETURN output, e.g. the
+    /// parachain account or issue id committed by a user.
+    ///
+    /// # Arguments
+    /// * `output` - transaction output carrying the OP_RETURN script
+    pub fn extract_op_return_data(output: &TransactionOutput) -> Result<Vec<u8>, Error> {
+        extract_op_return_data(&output.script).map_err(|_e| Error::InvalidOpreturn)
+    }
+
+    /// Returns the payload of the single OP_RETURN output of a
+    /// transaction. Standard transactions carry at most one OP_RETURN
+    /// output, more than one is rejected rather than picking one.
+    ///
+    /// # Arguments
+    /// * `transaction` - parsed Bitcoin transaction
+    pub fn get_op_return(transaction: &Transaction) -> Result<Vec<u8>, Error> {
+        Self::get_op_return_from_outputs(&transaction.outputs)
+    }
+
+    /// Returns the payload of the single OP_RETURN output among the
+    /// outputs of a transaction, see `get_op_return`.
+    ///
+    /// # Arguments
+    /// * `outputs` - outputs of a Bitcoin transaction
+    pub fn get_op_return_from_outputs(outputs: &[TransactionOutput]) -> Result<Vec<u8>, Error> {
+        let mut data_outputs = outputs
+            .iter()
+            .filter(|output| output.script.first() == Some(&(OpCode::OpReturn as u8)));
+
+        let data_output = data_outputs.next().ok_or(Error::TxFormat)?;
+        ensure!(data_outputs.next().is_none(), Error::InvalidOpreturn);
+        Self::extract_op_return_data(data_output)
+    }
+
+    /// Checks in one go that a transaction pays at least `amount` to the
+    /// vault and commits the issue id in its single OP_RETURN output, so
+    /// that the payment and the commitment cannot be matched against
+    /// different transactions. These are the checks of the
+    /// validate_transaction dispatchable without the strict checks.
+    ///
+    /// # Arguments
+    /// * `raw_tx` - raw Bitcoin transaction
+    /// * `vault_address` - 20 byte address hash or 32 byte witness
+    /// program of the vault
+    /// * `amount` - minimum value of BTC sent to the vault
+    /// * `issue_id` - issue id expected in OP_RETURN
+    ///
+    /// # Errors
+    /// The error of the first failed check:
+    /// * `MalformedAddress` - if the vault address has an unsupported length
+    /// * `TxFormat` - if the transaction is malformed, has fewer than two
+    /// outputs or no OP_RETURN output
+    /// * `WrongRecipient` - if no output pays the vault
+    /// * `InsufficientValue` - if the outputs pay the vault less than `amount`
+    /// * `InvalidOpreturn` - if there are several OP_RETURN outputs or
+    /// the OP_RETURN output does not commit the issue id
+    pub fn validate_issue_payment(
+        raw_tx: &[u8],
+        vault_address: &[u8],
+        amount: i64,
+        issue_id: &[u8],
+    ) -> Result<(), Error> {
+        let vault_address = Recipient::from_slice(vault_address)
+            .ok_or(Error::MalformedAddress)?;
+
+        // only the outputs are needed, the inputs are skipped
+        let outputs = Self::parse_transaction_outputs(raw_tx)?;
+
+        ensure!(outputs.len() >= MIN_PAYMENT_TX_OUTPUTS, Error::TxFormat);
+
+        // Sum up the value of all payment UTXOs sending to the vault,
+        // failing if no payment UTXO sends to the correct address
+        let extr_payment_value = Self::get_payment_value(&outputs, vault_address.as_bytes())
+            .ok_or(Error::WrongRecipient)?;
+
+        // Check if the payment UTXOs transfer sufficient value
+        ensure!(extr_payment_value >= amount, Error::InsufficientValue);
+
+        // Check if the single data UTXO has correct OP_RETURN value
+        let extr_op_return_value = Self::get_op_return_from_outputs(&outputs)?;
+        ensure!(extr_op_return_value.as_slice() == issue_id, Error::InvalidOpreturn);
+
+        Ok(())
+    }
+
+    /// Returns the value paid to a recipient, summed over all outputs
+    /// paying the recipient that are not below DustValue, or `None` if
+    /// no output pays the recipient. Data outputs and outputs of other
+    /// formats (e.g. change) are ignored.
+    ///
+    /// # Arguments
+    /// * `outputs` - outputs of a Bitcoin transaction
+    /// * `recipient` - 20 byte address hash or 32 byte witness program
+    pub fn get_payment_value(outputs: &[TransactionOutput], recipient: &[u8]) -> Option<i64> {
+        let mut recipient_found = false;
+        let mut payment_value: i64 = 0;
+        for output in outputs.iter() {
+            match output.script.first() {
+                Some(opcode) if *opcode == OpCode::OpReturn as u8 => (),
+                Some(_) => match extract_address_hash(&output.script) {
+                    Ok(address) if address.as_slice() == recipient => {
+                        recipient_found = true;
+                        // dust outputs do not count towards the payment
+                        if output.value >= Self::get_dust_value() {
+                            payment_value = payment_value.saturating_add(output.value);
+                        }
+                    }
+                    _ => (),
+                },
+                None => (),
+            }
+        }
+        match recipient_found {
+            true => Some(payment_value),
+            false => None,
+        }
+    }
+
+    /// Checks that each `(recipient, value)` pair is paid by the outputs,
+    /// returning the index of the first unpaid pair together with
+    /// WrongRecipient or InsufficientValue otherwise
+    ///
+    /// # Arguments
+    /// * `outputs` - outputs of a Bitcoin transaction
+    /// * `expected_payments` - pairs of a recipient and the minimum value
+    /// sent to it
+    pub fn check_payments(
+        outputs: &[TransactionOutput],
+        expected_payments: &[(Recipient, i64)],
+    ) -> Result<(), (u32, Error)> {
+        for (index, (recipient, value)) in expected_payments.iter().enumerate() {
+            match Self::get_payment_value(outputs, recipient.as_bytes()) {
+                None => return Err((index as u32, Error::WrongRecipient)),
+                Some(paid) if paid < *value => return Err((index as u32, Error::InsufficientValue)),
+                Some(_) => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the target the next block on top of the main chain tip
+    /// must use. Allows relayers to pre-validate headers before submission.
+    pub fn next_block_target() -> Result<U256, Error> {
+        let best_block_header = Self::get_block_header_from_hash(Self::get_best_block())?;
+        Self::expected_target(&best_block_header)
+    }
+
+    /// Returns a summary of the relay state for health endpoints.
+    ///
+    /// # Arguments
+    /// * `current_time` - current time in seconds since the unix epoch,
+    /// used to estimate the number of blocks the relay is behind
+    pub fn relay_status(current_time: u64) -> RelayStatus {
+        if !Self::best_block_exists() {
+            return RelayStatus::default();
+        }
+
+        let best_hash = Self::get_best_block();
+        let best_time = Self::get_block_header_from_hash(best_hash)
+            .map(|header| header.block_header.timestamp)
+            .unwrap_or_default();
+        let chain_count = Self::get_chain_count();
+
+        RelayStatus {
+            initialized: true,
+            best_height: Self::get_best_block_height(),
+            best_hash: best_hash,
+            best_time: best_time,
+            chain_count: chain_count,
+            fork_count: chain_count.saturating_sub(1),
+            main_chain_work: Self::get_block_chain_from_id(MAIN_CHAIN_ID).total_work,
+            last_reorg_depth: Self::get_last_reorg_depth(),
+            blocks_behind: (current_time.saturating_sub(best_time) / TARGET_SPACING) as u32,
+        }
+    }
+
+    /// Returns the cumulative work of the blocks of a chain starting at
+    /// the given height
+    /// # Arguments
+    ///  * `blockchain`: the chain to sum the work of
+    ///  * `from_height`: height of the first block to include
+    fn chain_work(blockchain: &BlockChain, from_height: u32) -> U256 {
+        blockchain
+            .chain
+            .range(from_height..)
+            .filter_map(|(_height, hash)| Self::get_block_header_from_hash(*hash).ok())
+            .fold(U256::zero(), |work, header| {
+                work.saturating_add(Self::block_work(header.block_header.target))
+            })
+    }
+
+    /// Returns the cumulative work of a chain up to and including the
+    /// given height, derived from the chain's total work
+    /// # Arguments
+    ///  * `blockchain`: the chain holding the block at `height`
+    ///  * `height`: height of the last block to include
+    fn chain_work_at(blockchain: &BlockChain, height: u32) -> U256 {
+        blockchain
+            .total_work
+            .saturating_sub(Self::chain_work(blockchain, height + 1))
+    }
+
+    /// Returns the lead of a fork over the main chain as the difference
+    /// in height and the work of the fork above the main chain's work
+    /// since the fork point, or zero if the fork has less work.
+    ///
+    /// # Arguments
+    /// * `chain_id` - id of the fork
+    pub fn fork_lead(chain_id: u32) -> Result<(i64, U256), Error> {
+        ensure!(Self::block_chain_exists(chain_id), Error::ForkIdNotFound);
+        let fork = Self::get_block_chain_from_id(chain_id);
+        let main_chain = Self::get_block_chain_from_id(MAIN_CHAIN_ID);
+
+        let height_lead = fork.max_height as i64 - main_chain.max_height as i64;
+        let work_lead = Self::chain_work(&fork, fork.start_height)
+            .saturating_sub(Self::chain_work(&main_chain, fork.start_height));
+
+        Ok((height_lead, work_lead))
+    }
+
+    /// Returns the expected number of hashes needed to mine a block
+    /// with the given target, i.e. 2^256 / (target + 1)
+    fn block_work(target: U256) -> U256 {
+        // a zero target is invalid and carries no work
+        if target.is_zero() {
+            return U256::zero();
+        }
+        // 2^256 does not fit a U256, but 2^256 / (target + 1) is equal to
+        // (~target / (target + 1)) + 1
+        if target == U256::max_value() {
+            return U256::one();
+        }
+        (!target / (target + 1)) + 1
+    }
+
+    /// Checks if two stored block headers are siblings, i.e. build on
+    /// the same parent block.
+    ///
+    /// # Arguments
+    /// * `hash_a` - hash of the first block header
+    /// * `hash_b` - hash of the second block header
+    pub fn are_siblings(hash_a: H256Le, hash_b: H256Le) -> Result<bool, Error> {
+        let header_a = Self::get_block_header_from_hash(hash_a)?;
+        let header_b = Self::get_block_header_from_hash(hash_b)?;
+        Ok(header_a.block_header.hash_prev_block
+            == header_b.block_header.hash_prev_block)
+    }
 }
 
 decl_event! {
@@ -1068,11 +3138,44 @@ decl_event! {
         Initialized(u32, H256Le),
         StoreMainChainHeader(u32, H256Le),
         StoreForkHeader(u32, u32, H256Le),
-        ChainReorg(H256Le, u32, u32),
+        /// Height of the first and last stored header, number of stored
+        /// headers
+        StoredHeaderBatch(u32, u32, u32),
+        /// Number of headers stored by the batch (without skipped known
+        /// headers), index of the failing header in the batch, error code
+        BatchPartiallyApplied(u32, u32, u8),
+        /// New best block hash, new best block height, fork depth
+        /// (max_height - start_height of the fork), old best block hash
+        ChainReorg(H256Le, u32, u32, H256Le),
+        /// Height of the first block and hashes in height order of a page
+        /// of at most MAX_PAGE_SIZE blocks removed from the main chain by
+        /// a reorg
+        BlocksOrphaned(u32, Vec<H256Le>),
         ForkAheadOfMainChain(u32, u32, u32),
+        /// Fork id, depth of the refused reorg
+        ReorgTooDeep(u32, u32),
         VerifyTransaction(H256Le, u32, u32),
-        ValidateTransaction(H256Le, u32, H160, H256Le),
+        /// Transaction id, payment value, recipient address hash or
+        /// witness program, OP_RETURN id
+        ValidateTransaction(H256Le, i64, Recipient, Vec<u8>),
+        /// Transaction id, pairs of recipient and payment value, OP_RETURN id
+        ValidateTransactionMulti(H256Le, Vec<(Recipient, i64)>, Vec<u8>),
+        /// Index of the first unpaid pair of validate_transaction_multi,
+        /// error code
+        PaymentRejected(u32, u8),
         FlagBlockError(H256Le, u32, ErrorCode),
         ClearBlockError(H256Le, u32, ErrorCode),
+        BlockErrorResolved(H256Le, u32, ErrorCode, ErrorResolution),
+        PrunedHeaders(u32, u32),
+        /// Id of the promoted fork, new best block hash, old best block hash
+        ForcedReorg(u32, H256Le, H256Le),
+        /// Height and hash of a resubmitted header that is already stored
+        DuplicateHeaderIgnored(u32, H256Le),
+        /// New and previous number of stable confirmations
+        StableConfirmationsSet(u32, u32),
+        /// Chain id, new and previous total work of the chain
+        ChainWorkSet(u32, U256, U256),
+        /// Block hash, witness merkle root of the block
+        StoreWitnessRoot(H256Le, H256Le),
     }
 }
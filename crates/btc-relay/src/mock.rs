@@ -1,8 +1,13 @@
 /// Mocking the test environment
-use crate::{Module, Trait};
+use crate::{
+    MainChainPruningFilter, Module, OnReorgHandler, ParachainStatus, StakedRelayers, Trait,
+};
+use bitcoin::types::{BitcoinNetwork, H256Le};
+use std::cell::RefCell;
 use frame_support::{
     impl_outer_event, impl_outer_origin, parameter_types, weights::Weight,
 };
+use security::StatusCode;
 use sp_core::H256;
 use sp_runtime::{
     testing::Header,
@@ -54,23 +59,134 @@ impl system::Trait for Test {
     type ModuleToIndex = ();
 }
 
+parameter_types! {
+    pub const MinimumPeriod: u64 = 5;
+}
+
+impl timestamp::Trait for Test {
+    type Moment = u64;
+    type OnTimestampSet = ();
+    type MinimumPeriod = MinimumPeriod;
+}
+
+thread_local! {
+    pub static REORGS: RefCell<Vec<(u32, Vec<H256Le>)>> = RefCell::new(Vec::new());
+    pub static PARACHAIN_STATUS: RefCell<StatusCode> = RefCell::new(StatusCode::Running);
+    pub static LOWEST_REFERENCED_HEIGHT: RefCell<Option<u32>> = RefCell::new(None);
+}
+
+/// On-chain time (in milliseconds) set in the test environment,
+/// 2020-05-01 00:00:00 UTC
+pub const CURRENT_TIME: u64 = 1_588_291_200_000;
+
+/// Records the reorgs reported by the relay
+pub struct MockReorgHandler;
+
+impl OnReorgHandler for MockReorgHandler {
+    fn on_reorg(fork_depth: u32, orphaned_blocks: Vec<H256Le>) {
+        REORGS.with(|r| r.borrow_mut().push((fork_depth, orphaned_blocks)));
+    }
+}
+
+/// Reports the height set in LOWEST_REFERENCED_HEIGHT
+pub struct MockPruningFilter;
+
+impl MainChainPruningFilter for MockPruningFilter {
+    fn lowest_referenced_height() -> Option<u32> {
+        LOWEST_REFERENCED_HEIGHT.with(|h| *h.borrow())
+    }
+}
+
+/// The only staked relayer in the test environment
+pub const STAKED_RELAYER: u64 = 1;
+
+pub struct MockStakedRelayers;
+
+impl StakedRelayers<u64> for MockStakedRelayers {
+    fn ensure_staked_relayer(relayer: &u64) -> Result<(), Error> {
+        match *relayer == STAKED_RELAYER {
+            true => Ok(()),
+            false => Err(Error::UnauthorizedRelayer),
+        }
+    }
+}
+
+/// Reports the status set in PARACHAIN_STATUS
+pub struct MockParachainStatus;
+
+impl ParachainStatus for MockParachainStatus {
+    fn ensure_not_shutdown() -> Result<(), Error> {
+        match PARACHAIN_STATUS.with(|s| *s.borrow() == StatusCode::Shutdown) {
+            true => Err(Error::Shutdown),
+            false => Ok(()),
+        }
+    }
+
+    fn ensure_parachain_running() -> Result<(), Error> {
+        Self::ensure_not_shutdown()?;
+        match PARACHAIN_STATUS.with(|s| *s.borrow() == StatusCode::Running) {
+            true => Ok(()),
+            false => Err(Error::Invalid),
+        }
+    }
+}
+
+parameter_types! {
+    pub const RetargetMaxFactor: u32 = 4;
+    pub const RetargetMinFactor: Perbill = Perbill::from_percent(25);
+    pub const Network: BitcoinNetwork = BitcoinNetwork::Mainnet;
+    pub const PruningWindow: u32 = 100;
+    pub const RetainedDepth: u32 = 100;
+    pub const DustValue: i64 = 546;
+    pub const MaxReorgDepth: u32 = 1000;
+    pub const StableBitcoinConfirmations: u32 = 6;
+}
+
 impl Trait for Test {
     type Event = TestEvent;
+    type OnReorg = MockReorgHandler;
+    type PruningFilter = MockPruningFilter;
+    type RetargetMaxFactor = RetargetMaxFactor;
+    type RetargetMinFactor = RetargetMinFactor;
+    type Network = Network;
+    type StakedRelayers = MockStakedRelayers;
+    type ParachainStatus = MockParachainStatus;
+    type PruningWindow = PruningWindow;
+    type RetainedDepth = RetainedDepth;
+    type DustValue = DustValue;
+    type MaxReorgDepth = MaxReorgDepth;
+    type StableBitcoinConfirmations = StableBitcoinConfirmations;
+    type WeightInfo = ();
 }
 
 pub type Error = crate::Error;
 
 pub type System = system::Module<Test>;
+pub type Timestamp = timestamp::Module<Test>;
 pub type BTCRelay = Module<Test>;
 
 pub struct ExtBuilder;
 
 impl ExtBuilder {
     pub fn build() -> sp_io::TestExternalities {
-        let storage = system::GenesisConfig::default()
+        Self::build_with_genesis(vec![], 0)
+    }
+
+    /// Builds the test environment with the relay seeded from the given
+    /// genesis header (left uninitialized if empty)
+    pub fn build_with_genesis(genesis_header: Vec<u8>, genesis_height: u32) -> sp_io::TestExternalities {
+        let mut storage = system::GenesisConfig::default()
             .build_storage::<Test>()
             .unwrap();
-        sp_io::TestExternalities::from(storage)
+        crate::GenesisConfig {
+            genesis_header,
+            genesis_height,
+        }
+        .assimilate_storage::<Test>(&mut storage)
+        .unwrap();
+        let mut ext = sp_io::TestExternalities::from(storage);
+        ext.execute_with(|| Timestamp::set_timestamp(CURRENT_TIME));
+        ext
     }
 }
 
@@ -21,6 +21,9 @@ use sp_api::impl_runtime_apis;
 use sp_consensus_aura::sr25519::AuthorityId as AuraId;
 use grandpa::AuthorityList as GrandpaAuthorityList;
 use grandpa::fg_primitives;
+use btc_relay_runtime_api::{
+	BitcoinNetwork, H256Le, InclusionVerification, RelayStatus, RichBlockHeader, U256,
+};
 use sp_version::RuntimeVersion;
 #[cfg(feature = "std")]
 use sp_version::NativeVersion;
@@ -236,8 +239,42 @@ impl sudo::Trait for Runtime {
 }
 
 /// Integrating the BTC-Relay pallet
+parameter_types! {
+	pub const RetargetMaxFactor: u32 = 4;
+	pub const RetargetMinFactor: Perbill = Perbill::from_percent(25);
+	pub const Network: BitcoinNetwork = BitcoinNetwork::Mainnet;
+	pub const PruningWindow: u32 = 2016;
+	pub const RetainedDepth: u32 = 4032;
+	pub const DustValue: i64 = 546;
+	pub const MaxReorgDepth: u32 = 100;
+	pub const StableBitcoinConfirmations: u32 = 6;
+}
+
+/// The security pallet is not part of the runtime yet, so no account
+/// is allowed to flag or clear block errors
+pub struct NoStakedRelayers;
+
+impl btc_relay::StakedRelayers<AccountId> for NoStakedRelayers {
+    fn ensure_staked_relayer(_relayer: &AccountId) -> Result<(), btc_relay::Error> {
+        Err(btc_relay::Error::UnauthorizedRelayer)
+    }
+}
+
 impl btc_relay::Trait for Runtime {
     type Event = Event;
+    type OnReorg = ();
+    type PruningFilter = ();
+    type RetargetMaxFactor = RetargetMaxFactor;
+    type RetargetMinFactor = RetargetMinFactor;
+    type Network = Network;
+    type StakedRelayers = NoStakedRelayers;
+    type ParachainStatus = ();
+    type PruningWindow = PruningWindow;
+    type RetainedDepth = RetainedDepth;
+    type DustValue = DustValue;
+    type MaxReorgDepth = MaxReorgDepth;
+    type StableBitcoinConfirmations = StableBitcoinConfirmations;
+    type WeightInfo = ();
 }
 
 /// Used for the module template in `./template.rs`
@@ -261,7 +298,7 @@ construct_runtime!(
 		TransactionPayment: transaction_payment::{Module, Storage},
 		Sudo: sudo,
         // BTC-Relay pallet
-        BTCRelay: btc_relay::{Module, Call, Storage, Event<T>},
+        BTCRelay: btc_relay::{Module, Call, Storage, Event<T>, Config},
 		RandomnessCollectiveFlip: randomness_collective_flip::{Module, Call, Storage},
 	}
 );
@@ -371,4 +408,132 @@ impl_runtime_apis! {
 			Grandpa::grandpa_authorities()
 		}
 	}
+
+	impl btc_relay_runtime_api::BTCRelayApi<Block> for Runtime {
+		fn best_block_hash() -> H256Le {
+			BTCRelay::get_best_block()
+		}
+
+		fn best_block_height() -> u32 {
+			BTCRelay::get_best_block_height()
+		}
+
+		fn is_fully_initialized() -> bool {
+			BTCRelay::is_fully_initialized()
+		}
+
+		fn chain_tip_for(chain_id: u32) -> Option<(u32, H256Le)> {
+			BTCRelay::chain_tip_for(chain_id)
+		}
+
+		fn script_matches_recipient(
+			script: Vec<u8>,
+			recipient: Vec<u8>,
+			network: BitcoinNetwork,
+		) -> bool {
+			BTCRelay::script_matches_recipient(script, recipient, network)
+		}
+
+		fn block_depth(block_hash: H256Le) -> Option<u32> {
+			BTCRelay::block_depth(block_hash).ok()
+		}
+
+		fn blocks_behind_tip(block_hash: H256Le) -> Option<u32> {
+			BTCRelay::blocks_behind_tip(block_hash)
+		}
+
+		fn is_block_main_chain_tip(block_hash: H256Le) -> bool {
+			BTCRelay::is_block_main_chain_tip(block_hash)
+		}
+
+		fn get_chain_ref_for_block(block_hash: H256Le) -> Option<u32> {
+			BTCRelay::get_chain_ref_for_block(block_hash).ok()
+		}
+
+		fn is_block_canonical(block_hash: H256Le) -> bool {
+			BTCRelay::is_block_canonical(block_hash)
+		}
+
+		fn get_bits_at_height(block_height: u32) -> Option<u32> {
+			BTCRelay::get_bits_at_height(block_height).ok()
+		}
+
+		fn headers_at_height(block_height: u32, from: u32, limit: u32) -> Vec<RichBlockHeader> {
+			BTCRelay::headers_at_height(block_height, from, limit)
+		}
+
+		fn dump_chain(chain_id: u32, from: u32, limit: u32) -> Vec<RichBlockHeader> {
+			BTCRelay::dump_chain(chain_id, from, limit)
+		}
+
+		fn next_block_target() -> Option<U256> {
+			BTCRelay::next_block_target().ok()
+		}
+
+		fn relay_status(current_time: u64) -> RelayStatus {
+			BTCRelay::relay_status(current_time)
+		}
+
+		fn fork_lead(chain_id: u32) -> Option<(i64, U256)> {
+			BTCRelay::fork_lead(chain_id).ok()
+		}
+
+		fn get_main_chain_block_hash(block_height: u32) -> Option<H256Le> {
+			BTCRelay::get_main_chain_block_hash(block_height)
+		}
+
+		fn get_main_chain_hashes(from_height: u32, count: u32) -> Vec<(u32, H256Le)> {
+			BTCRelay::get_main_chain_hashes(from_height, count)
+		}
+
+		fn get_ongoing_forks() -> Vec<(u32, u32, u32)> {
+			BTCRelay::get_ongoing_forks()
+		}
+
+		fn verify_transaction_inclusion(
+			tx_id: H256Le,
+			block_height: u32,
+			raw_merkle_proof: Vec<u8>,
+			confirmations: u32,
+			insecure: bool,
+		) -> InclusionVerification {
+			match BTCRelay::verify_transaction_inclusion_unsigned(
+				tx_id, block_height, &raw_merkle_proof, confirmations, insecure
+			) {
+				Ok(()) => InclusionVerification::Verified,
+				Err(error) => InclusionVerification::Rejected(error as u8),
+			}
+		}
+	}
+
+	#[cfg(feature = "runtime-benchmarks")]
+	impl frame_benchmarking::Benchmark<Block> for Runtime {
+		fn dispatch_benchmark(
+			pallet: Vec<u8>,
+			benchmark: Vec<u8>,
+			lowest_range_values: Vec<u32>,
+			highest_range_values: Vec<u32>,
+			steps: Vec<u32>,
+			repeat: u32,
+		) -> Result<Vec<frame_benchmarking::BenchmarkBatch>, sp_runtime::RuntimeString> {
+			use frame_benchmarking::{Benchmarking, BenchmarkBatch, add_benchmark};
+
+			let mut batches = Vec::<BenchmarkBatch>::new();
+			let params = (
+				&pallet,
+				&benchmark,
+				&lowest_range_values,
+				&highest_range_values,
+				&steps,
+				repeat,
+			);
+
+			add_benchmark!(params, batches, b"btc-relay", BTCRelay);
+
+			if batches.is_empty() {
+				return Err("Benchmark not found for this pallet.".into())
+			}
+			Ok(batches)
+		}
+	}
 }
@@ -1,6 +1,6 @@
 use sp_core::{Pair, Public, sr25519};
 use btc_parachain_runtime::{
-	AccountId, AuraConfig, BalancesConfig, GenesisConfig, GrandpaConfig,
+	AccountId, AuraConfig, BalancesConfig, BTCRelayConfig, GenesisConfig, GrandpaConfig,
 	SudoConfig, IndicesConfig, SystemConfig, WASM_BINARY, Signature
 };
 use sp_consensus_aura::sr25519::{AuthorityId as AuraId};
@@ -139,5 +139,10 @@ fn testnet_genesis(initial_authorities: Vec<(AuraId, GrandpaId)>,
 		grandpa: Some(GrandpaConfig {
 			authorities: initial_authorities.iter().map(|x| (x.1.clone(), 1)).collect(),
 		}),
+		// the relay is initialized through the initialize extrinsic
+		btc_relay: Some(BTCRelayConfig {
+			genesis_header: vec![],
+			genesis_height: 0,
+		}),
 	}
 }